@@ -0,0 +1,230 @@
+use std::str::FromStr;
+
+use glob::{MatchOptions, Pattern};
+
+use crate::chest::{CompressionAlgorithm, FileCompression};
+use crate::compression::{default_level, get_file_compressor};
+use crate::error::CompressResult;
+
+const SAMPLE_LENGTH: usize = 64 * 1024;
+// a sample has to shrink below this ratio for the whole file to be compressed
+const SAMPLE_MAX_RATIO: f64 = 0.95;
+const MIME_PREFIX: &str = "mime:";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+// magic bytes of common formats, with whether they are already compressed
+const MAGIC_BYTES: &[(&[u8], &str, bool)] = &[
+    (b"\xff\xd8\xff", "image/jpeg", true),
+    (b"\x89PNG\r\n\x1a\n", "image/png", true),
+    (b"GIF8", "image/gif", true),
+    (b"PK\x03\x04", "application/zip", true),
+    (b"\x1f\x8b", "application/gzip", true),
+    (b"\x28\xb5\x2f\xfd", "application/zstd", true),
+    (b"\xfd7zXZ\x00", "application/x-xz", true),
+    (b"BZh", "application/x-bzip2", true),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed", true),
+    (b"\x04\x22\x4d\x18", "application/x-lz4", true),
+    (b"Rar!\x1a\x07", "application/vnd.rar", true),
+    (b"ID3", "audio/mpeg", true),
+    (b"OggS", "audio/ogg", true),
+    (b"fLaC", "audio/flac", true),
+    (b"\x1a\x45\xdf\xa3", "video/webm", true),
+    (b"%PDF-", "application/pdf", false),
+    (b"SQLite format 3\x00", "application/vnd.sqlite3", false),
+    (b"\x7fELF", "application/x-executable", false),
+];
+// mp4 and quicktime files have their magic bytes after the box size
+const FTYP: &[u8] = b"ftyp";
+
+#[derive(Debug, Clone)]
+enum RuleTarget {
+    Filename(Pattern),
+    Mime(Pattern),
+}
+
+/// Compression override for the files matching a name or MIME type pattern,
+/// written as `PATTERN=ALGORITHM[:LEVEL]` or `mime:PATTERN=none`
+#[derive(Debug, Clone)]
+pub(crate) struct CompressionRule {
+    target: RuleTarget,
+    compression: Option<FileCompression>,
+}
+
+impl FromStr for CompressionRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let (pattern, compression) = rule
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected PATTERN=ALGORITHM, got {rule}"))?;
+        let target = match pattern.strip_prefix(MIME_PREFIX) {
+            Some(mime) => RuleTarget::Mime(Pattern::new(mime).map_err(|e| e.to_string())?),
+            None => RuleTarget::Filename(Pattern::new(pattern).map_err(|e| e.to_string())?),
+        };
+        let (algorithm, level) = match compression.split_once(':') {
+            Some((algorithm, level)) => (
+                algorithm,
+                Some(level.parse::<i32>().map_err(|e| e.to_string())?),
+            ),
+            None => (compression, None),
+        };
+        let compression = match algorithm {
+            "none" => None,
+            algorithm => {
+                let algorithm: CompressionAlgorithm = clap::ValueEnum::from_str(algorithm, true)?;
+                let level = level.unwrap_or_else(|| default_level(&algorithm));
+                Some(FileCompression {
+                    algorithm,
+                    level,
+                    dictionary: false,
+                })
+            }
+        };
+        Ok(Self {
+            target,
+            compression,
+        })
+    }
+}
+
+impl CompressionRule {
+    fn matches(&self, filename: &str, mime: Option<&str>) -> bool {
+        match &self.target {
+            RuleTarget::Filename(pattern) => pattern.matches_with(filename, MATCH_OPTIONS),
+            RuleTarget::Mime(pattern) => mime.is_some_and(|m| pattern.matches(m)),
+        }
+    }
+}
+
+pub(crate) fn sniff_mime(payload: &[u8]) -> Option<&'static str> {
+    if payload.get(4..8) == Some(FTYP) {
+        return Some("video/mp4");
+    }
+    MAGIC_BYTES
+        .iter()
+        .find(|(magic, _, _)| payload.starts_with(magic))
+        .map(|(_, mime, _)| *mime)
+}
+
+fn is_compressed_mime(mime: &str) -> bool {
+    mime == "video/mp4"
+        || MAGIC_BYTES
+            .iter()
+            .any(|(_, known, compressed)| *known == mime && *compressed)
+}
+
+/// Compress the payload with the first matching rule or the default
+/// compression, keeping it as is when compressing doesn't make it smaller
+pub(crate) fn compress(
+    rules: &[CompressionRule],
+    default: Option<FileCompression>,
+    dictionary: Option<&[u8]>,
+    filename: &str,
+    payload: Vec<u8>,
+) -> CompressResult<(Option<FileCompression>, Vec<u8>)> {
+    let mime = sniff_mime(payload.get(..SAMPLE_LENGTH).unwrap_or(&payload));
+    let rule = rules.iter().find(|r| r.matches(filename, mime));
+    let compression = match rule {
+        Some(rule) => rule.compression.clone(),
+        None if mime.is_some_and(is_compressed_mime) => None,
+        None => default,
+    };
+    let Some(compression) = compression else {
+        return Ok((None, payload));
+    };
+    let compressor = get_file_compressor(&compression, dictionary)?;
+    // explicit rules skip the sample test, but not the real result check
+    if rule.is_none() && payload.len() > SAMPLE_LENGTH {
+        let sample = &payload[..SAMPLE_LENGTH];
+        let compressed_sample = compressor.compress(sample)?;
+        if compressed_sample.len() as f64 > sample.len() as f64 * SAMPLE_MAX_RATIO {
+            return Ok((None, payload));
+        }
+    }
+    let compressed = compressor.compress(&payload)?;
+    if compressed.len() >= payload.len() {
+        return Ok((None, payload));
+    }
+    Ok((Some(compression), compressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZSTD: FileCompression = FileCompression {
+        algorithm: CompressionAlgorithm::Zstd,
+        level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        dictionary: false,
+    };
+
+    #[test]
+    fn rules_are_parsed() {
+        let rule = "*.log=zstd:19".parse::<CompressionRule>().unwrap();
+        assert!(rule.matches("app.LOG", None));
+        assert_eq!(
+            Some(FileCompression {
+                algorithm: CompressionAlgorithm::Zstd,
+                level: 19,
+                dictionary: false,
+            }),
+            rule.compression
+        );
+        let rule = "mime:image/*=none".parse::<CompressionRule>().unwrap();
+        assert!(rule.matches("photo", Some("image/png")));
+        assert!(!rule.matches("image/photo", None));
+        assert_eq!(None, rule.compression);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        assert!("*.log".parse::<CompressionRule>().is_err());
+        assert!("*.log=rar".parse::<CompressionRule>().is_err());
+        assert!("*.log=zstd:max".parse::<CompressionRule>().is_err());
+    }
+
+    #[test]
+    fn mime_is_sniffed_from_magic_bytes() {
+        assert_eq!(Some("image/png"), sniff_mime(b"\x89PNG\r\n\x1a\n...."));
+        assert_eq!(Some("video/mp4"), sniff_mime(b"\x00\x00\x00\x18ftypmp42"));
+        assert_eq!(None, sniff_mime(b"plain text"));
+    }
+
+    #[test]
+    fn compressible_payload_is_compressed() {
+        let payload = b"aaaaaaaaa".repeat(100);
+        let (compression, compressed) =
+            compress(&[], Some(ZSTD), None, "a.txt", payload.clone()).unwrap();
+        assert_eq!(Some(ZSTD), compression);
+        assert!(compressed.len() < payload.len());
+    }
+
+    #[test]
+    fn incompressible_payload_is_stored() {
+        let payload = crate::random::generate_random_bytes(SAMPLE_LENGTH * 2).unwrap();
+        let (compression, stored) =
+            compress(&[], Some(ZSTD), None, "a.bin", payload.clone()).unwrap();
+        assert_eq!(None, compression);
+        assert_eq!(payload, stored);
+    }
+
+    #[test]
+    fn already_compressed_mime_is_stored() {
+        let payload = [b"\x1f\x8b".as_slice(), &b"a".repeat(1000)].concat();
+        let (compression, _) = compress(&[], Some(ZSTD), None, "a.gz", payload).unwrap();
+        assert_eq!(None, compression);
+    }
+
+    #[test]
+    fn matching_rule_overrides_default() {
+        let rules = ["*.txt=none".parse::<CompressionRule>().unwrap()];
+        let payload = b"aaaaaaaaa".repeat(100);
+        let (compression, _) = compress(&rules, Some(ZSTD), None, "a.txt", payload).unwrap();
+        assert_eq!(None, compression);
+    }
+}
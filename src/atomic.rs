@@ -0,0 +1,91 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Path of the previous version kept by `--backup`, e.g. `name.chest~`
+pub(crate) fn backup_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push("~");
+    name.into()
+}
+
+/// Write the file to a temporary file in the same directory, synced to disk
+/// then renamed over the target, so a crash or a full disk leaves either the
+/// previous version or the new one
+pub(crate) fn write(path: &Path, payload: &[u8], backup: bool) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = dir.join(temp_name);
+    let written = write_synced(&temp_path, path, payload).and_then(|_| {
+        if backup && path.exists() {
+            keep_backup(path)?;
+        }
+        fs::rename(&temp_path, path)
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written?;
+    sync_dir(dir)
+}
+
+fn write_synced(temp_path: &Path, path: &Path, payload: &[u8]) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)?;
+    // keep the permissions of the file being replaced
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.write_all(payload)?;
+    file.sync_all()
+}
+
+/// Keep the current version next to the target, hard linked when possible so
+/// large chests aren't copied
+fn keep_backup(path: &Path) -> io::Result<()> {
+    let backup = backup_path(path);
+    match fs::remove_file(&backup) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+    if fs::hard_link(path, &backup).is_err() {
+        fs::copy(path, &backup)?;
+    }
+    Ok(())
+}
+
+/// Persist the rename itself, directories can't be synced on Windows
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_replaces_and_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.chest");
+        write(&path, b"first", true).unwrap();
+        assert!(!backup_path(&path).exists());
+        write(&path, b"second", false).unwrap();
+        assert!(!backup_path(&path).exists());
+        write(&path, b"third", true).unwrap();
+        assert_eq!(b"third", &fs::read(&path).unwrap()[..]);
+        assert_eq!(b"second", &fs::read(backup_path(&path)).unwrap()[..]);
+        assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
@@ -1,33 +1,90 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use clap::ValueEnum;
+use once_cell::sync::OnceCell;
+use ring::digest;
 use serde::{Deserialize, Serialize};
 
-use crate::compression::{get_compressor, Compress};
+use crate::adaptive::{self, CompressionRule};
+use crate::atomic;
+use crate::compression::{default_level, get_compressor, get_file_compressor, train_dictionary};
+use crate::conflict;
+use crate::container;
 use crate::crypto::{get_encryptor, Encrypt};
-use crate::error::{ChestError, ChestResult};
+use crate::dedup::{self, ChunkId, ChunkRef};
+use crate::error::{ChestError, ChestResult, CompressError};
+use crate::filter::FileFilter;
 use crate::key::{get_deriver, Derive};
+use crate::legacy;
+use crate::padding::{self, HiddenIndex, PaddedIndex, Padding};
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct UnlockedChest {
     key: Vec<u8>,
     pub(crate) public: Public,
     pub(crate) files: Vec<UnlockedFile>,
+    pub(crate) dictionary: Option<Vec<u8>>,
+    solid_stream: Option<EncryptedBlob>,
+    solid_compression: Option<FileCompression>,
+    chunks: BTreeMap<ChunkId, EncryptedBlob>,
+    pub(crate) snapshots: Vec<Snapshot>,
+    // percentage of parity data written with the chest, outside its payload
+    #[serde(skip)]
+    pub(crate) redundancy: Option<u8>,
+    // privacy mode, the index it is read from being part of the payload
+    #[serde(skip)]
+    pub(crate) padding: Option<Padding>,
+    // files hidden in the decoys of a padded chest, read-only
+    #[serde(skip)]
+    pub(crate) hidden: bool,
+    // solid stream being built, or decrypted and decompressed on first read
+    #[serde(skip)]
+    solid_plaintext: OnceCell<Vec<u8>>,
+    #[serde(skip)]
+    compression_rules: Vec<CompressionRule>,
 }
 
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Public {
     pub(crate) compression_algorithm: Option<CompressionAlgorithm>,
+    pub(crate) compression_level: Option<i32>,
+    pub(crate) layout: Layout,
     pub(crate) key_derivation_algorithm: KeyDerivationAlgorithm,
     pub(crate) key_derivation_salt: Vec<u8>,
     pub(crate) encryption_algorithm: EncryptionAlgorithm,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, ValueEnum)]
 pub(crate) enum CompressionAlgorithm {
     #[default]
     Deflate,
+    Zstd,
+    Lz4,
+    Brotli,
+    Xz,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Layout {
+    /// Each file is compressed and encrypted in its own blob
+    #[default]
+    Blobs,
+    /// All files are compressed and encrypted as a single stream
+    Solid,
+    /// Files are split in chunks, each unique chunk being compressed and
+    /// encrypted once
+    Dedup,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FileCompression {
+    pub(crate) algorithm: CompressionAlgorithm,
+    pub(crate) level: i32,
+    pub(crate) dictionary: bool,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -44,11 +101,47 @@ pub(crate) enum KeyDerivationAlgorithm {
 
 #[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct UnlockedFile {
-    pub(crate) cipher: EncryptedBlob,
+    pub(crate) content: Content,
     pub(crate) metadata: Metadata,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
+pub(crate) enum Content {
+    Blob(EncryptedBlob),
+    /// Offset of the file in the solid stream, its length being its size
+    Solid(u64),
+    Chunks(Vec<ChunkRef>),
+}
+
+impl UnlockedFile {
+    /// Stored size of the file, unknown for files of a solid stream
+    pub(crate) fn stored_bytes(&self) -> Option<u64> {
+        match &self.content {
+            Content::Blob(blob) => Some(blob.cipher.len() as u64),
+            Content::Solid(_) | Content::Chunks(_) => None,
+        }
+    }
+}
+
+/// Version of the chest content, sharing the files unchanged since the
+/// previous snapshot
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    pub(crate) created: u64,
+    /// Indices of the snapshot files in the chest files
+    pub(crate) files: Vec<usize>,
+}
+
+impl Snapshot {
+    pub(crate) fn new() -> Self {
+        Self {
+            created: now(),
+            files: Vec::new(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct EncryptedBlob {
     pub(crate) cipher: Vec<u8>,
     pub(crate) salt: Vec<u8>,
@@ -59,12 +152,52 @@ pub(crate) struct EncryptedBlob {
 pub(crate) struct Metadata {
     pub(crate) filename: String,
     pub(crate) size_bytes: u64,
+    pub(crate) modified: Option<u64>,
+    /// `None` for files of first format chests until the chest is locked again
+    pub(crate) sha256: Option<[u8; 32]>,
+    pub(crate) compression: Option<FileCompression>,
 }
 
+// payloads written before the format version start with the compression
+// option tag, 0 or 1, so versions start at 2
+const FORMAT_VERSION: u16 = 11;
+
+/// Chest as written to disk, a new field must also be read by `from_partial`
+/// in the same order, and a changed field needs a new `FORMAT_VERSION`
 #[derive(Serialize, Deserialize)]
 pub(crate) struct LockedChest {
+    format: u16,
     public: Public,
+    /// Metadata and blob lengths of a padded chest
+    index: Option<EncryptedBlob>,
+    /// Index of the files hidden in the decoys, random bytes when unused
+    hidden_index: Option<EncryptedBlob>,
     files: Vec<LockedFile>,
+    dictionary: Option<EncryptedBlob>,
+    solid: Option<LockedSolid>,
+    chunks: BTreeMap<ChunkId, EncryptedBlob>,
+    snapshots: Option<EncryptedBlob>,
+    #[serde(skip)]
+    redundancy: Option<u8>,
+    // first format chest, migrated when unlocked
+    #[serde(skip)]
+    legacy: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct LockedSolid {
+    pub(crate) index: EncryptedBlob,
+    pub(crate) stream: EncryptedBlob,
+}
+
+// first byte of a decrypted chunk, telling whether the rest is compressed
+const CHUNK_RAW: u8 = 0;
+const CHUNK_COMPRESSED: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SolidIndex {
+    compression: Option<FileCompression>,
+    files: Vec<(Metadata, u64)>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -74,97 +207,670 @@ pub(crate) struct LockedFile {
 }
 
 impl UnlockedChest {
-    pub(crate) fn new(password: &str, compress: bool) -> Self {
+    pub(crate) fn new(
+        password: &str,
+        compression_algorithm: Option<CompressionAlgorithm>,
+        compression_level: Option<i32>,
+        layout: Layout,
+    ) -> ChestResult<Self> {
+        if let Some(algorithm) = &compression_algorithm {
+            get_compressor(algorithm, compression_level)?;
+        }
         let public = Public {
-            compression_algorithm: compress.then_some(CompressionAlgorithm::default()),
+            compression_level: compression_algorithm
+                .as_ref()
+                .map(|a| compression_level.unwrap_or_else(|| default_level(a))),
+            compression_algorithm,
+            layout,
             ..Public::default()
         };
         let deriver = get_deriver(&public.key_derivation_algorithm);
         let key = deriver.derive(password, &public.key_derivation_salt);
         let files = Vec::default();
-        Self { key, public, files }
+        Ok(Self {
+            key,
+            public,
+            files,
+            dictionary: None,
+            solid_stream: None,
+            solid_compression: None,
+            chunks: BTreeMap::default(),
+            snapshots: Vec::default(),
+            redundancy: None,
+            padding: None,
+            hidden: false,
+            solid_plaintext: OnceCell::new(),
+            compression_rules: Vec::default(),
+        })
+    }
+
+    /// Empty chest for files hidden in this padded chest, opened by a second
+    /// password deriving its key with the same salt
+    pub(crate) fn new_hidden(&self, password: &str) -> ChestResult<Self> {
+        if self.hidden {
+            return Err(ChestError::Hidden("hidden files can't hide other files"));
+        }
+        if self.padding.as_ref().is_none_or(|p| p.decoys.is_empty()) {
+            return Err(ChestError::Hidden(
+                "the chest must be created with --pad and --decoys",
+            ));
+        }
+        let deriver = get_deriver(&self.public.key_derivation_algorithm);
+        let key = deriver.derive(password, &self.public.key_derivation_salt);
+        if key == self.key {
+            return Err(ChestError::Hidden(
+                "the hidden password must differ from the chest password",
+            ));
+        }
+        Ok(Self {
+            key,
+            public: Public {
+                layout: Layout::Blobs,
+                ..self.public.clone()
+            },
+            files: Vec::default(),
+            dictionary: None,
+            solid_stream: None,
+            solid_compression: None,
+            chunks: BTreeMap::default(),
+            snapshots: Vec::default(),
+            redundancy: None,
+            padding: None,
+            hidden: true,
+            solid_plaintext: OnceCell::new(),
+            compression_rules: self.compression_rules.clone(),
+        })
+    }
+
+    /// Store the files of the hidden chest in the decoys, largest first in
+    /// the smallest decoy they fit in, replacing previously hidden files
+    pub(crate) fn hide(&mut self, hidden: UnlockedChest) -> ChestResult<()> {
+        let padding = self
+            .padding
+            .as_mut()
+            .ok_or(ChestError::Hidden("the chest must be created with --pad"))?;
+        let decoys = padding.decoys.len();
+        let mut free = (0..decoys).collect::<Vec<_>>();
+        let mut index = HiddenIndex::default();
+        let mut files = hidden.files;
+        files.sort_by_key(|f| std::cmp::Reverse(f.stored_bytes()));
+        for f in files {
+            let Content::Blob(blob) = f.content else {
+                unreachable!("hidden files are stored as blobs");
+            };
+            let len = blob.cipher.len();
+            let slot = free
+                .iter()
+                .copied()
+                .filter(|i| padding.decoys[*i].cipher.len() >= len)
+                .min_by_key(|i| padding.decoys[*i].cipher.len())
+                .ok_or_else(|| ChestError::NoDecoySpace(f.metadata.filename.clone()))?;
+            free.retain(|i| *i != slot);
+            let decoy = &mut padding.decoys[slot];
+            decoy.cipher[..len].copy_from_slice(&blob.cipher);
+            decoy.salt = blob.salt;
+            decoy.nonce = blob.nonce;
+            index.metadata.push(f.metadata);
+            index.from_end.push(decoys - slot);
+            index.lengths.push(len as u64);
+        }
+        let encryptor = get_encryptor(&self.public.encryption_algorithm);
+        let index = padding::seal_index(&encryptor, &hidden.key, bincode::serialize(&index)?)?;
+        if index.cipher.len() > padding::hidden_index_room(decoys) {
+            return Err(ChestError::Hidden(
+                "the hidden file names are too long for the chest index",
+            ));
+        }
+        padding.hidden_index = Some(index);
+        Ok(())
+    }
+
+    pub(crate) fn set_compression_rules(&mut self, rules: Vec<CompressionRule>) {
+        self.compression_rules = rules;
+    }
+
+    /// Train a compression dictionary on the samples, used to compress every
+    /// file added afterward
+    pub(crate) fn train_dictionary<S: AsRef<[u8]>>(
+        &mut self,
+        samples: &[S],
+        max_size: usize,
+    ) -> ChestResult<()> {
+        let algorithm = self
+            .public
+            .compression_algorithm
+            .clone()
+            .unwrap_or_default();
+        if algorithm != CompressionAlgorithm::Zstd {
+            return Err(CompressError::DictionaryUnsupported(algorithm).into());
+        }
+        self.dictionary = Some(train_dictionary(samples, max_size)?);
+        Ok(())
+    }
+
+    fn default_compression(&self) -> Option<FileCompression> {
+        self.public
+            .compression_algorithm
+            .as_ref()
+            .map(|a| FileCompression {
+                algorithm: a.clone(),
+                level: self
+                    .public
+                    .compression_level
+                    .unwrap_or_else(|| default_level(a)),
+                dictionary: self.dictionary.is_some(),
+            })
     }
 
     pub(crate) fn add_file_from_cipher(
         &mut self,
         cipher: Vec<u8>,
-        metadata: Metadata,
+        mut metadata: Metadata,
     ) -> ChestResult<()> {
+        if self.public.layout == Layout::Solid {
+            self.solid_plaintext()?;
+            let stream = self
+                .solid_plaintext
+                .get_mut()
+                .expect("Solid stream is loaded");
+            let offset = stream.len() as u64;
+            stream.extend_from_slice(&cipher);
+            self.files.push(UnlockedFile {
+                content: Content::Solid(offset),
+                metadata,
+            });
+            return Ok(());
+        }
+        if self.public.layout == Layout::Dedup {
+            let chunks = self.add_chunks(&cipher)?;
+            self.files.push(UnlockedFile {
+                content: Content::Chunks(chunks),
+                metadata,
+            });
+            return Ok(());
+        }
         let encryptor = get_encryptor(&self.public.encryption_algorithm);
-        let cipher = match &self.public.compression_algorithm {
-            Some(compression_algorithm) => {
-                let compressor = get_compressor(compression_algorithm);
-                compressor.compress(&cipher)?
-            }
-            None => cipher,
-        };
+        let (compression, cipher) = adaptive::compress(
+            &self.compression_rules,
+            self.default_compression(),
+            self.dictionary.as_deref(),
+            &metadata.filename,
+            cipher,
+        )?;
+        metadata.compression = compression;
         let file = UnlockedFile {
-            cipher: encryptor.encrypt(cipher, &self.key.clone().try_into().unwrap())?,
+            content: Content::Blob(
+                encryptor.encrypt(cipher, &self.key.clone().try_into().unwrap())?,
+            ),
             metadata,
         };
         self.files.push(file);
         Ok(())
     }
 
+    fn add_chunks(&mut self, payload: &[u8]) -> ChestResult<Vec<ChunkRef>> {
+        let encryptor = get_encryptor(&self.public.encryption_algorithm);
+        let id_key = dedup::chunk_id_key(&self.key);
+        let default_compression = self.default_compression();
+        dedup::split(payload)
+            .map(|chunk| {
+                let id = dedup::chunk_id(&id_key, chunk);
+                if !self.chunks.contains_key(&id) {
+                    let (compression, stored) = adaptive::compress(
+                        &[],
+                        default_compression.clone(),
+                        None,
+                        "",
+                        chunk.to_vec(),
+                    )?;
+                    let flag = match compression {
+                        Some(_) => CHUNK_COMPRESSED,
+                        None => CHUNK_RAW,
+                    };
+                    let stored = [&[flag], stored.as_slice()].concat();
+                    let blob = encryptor.encrypt(stored, &self.key.clone().try_into().unwrap())?;
+                    self.chunks.insert(id, blob);
+                }
+                Ok(ChunkRef {
+                    id,
+                    length: chunk.len() as u32,
+                })
+            })
+            .collect()
+    }
+
     pub(crate) fn add_file_from_path<P: AsRef<Path>>(&mut self, path: P) -> ChestResult<()> {
-        let mut file = fs::File::open(&path)?;
-        let metadata = fs::File::metadata(&file)?;
+        let file = fs::File::open(&path)?;
+        let modified = file.metadata()?.modified().ok();
+        let filename = path
+            .as_ref()
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        self.add_file_from_reader(file, filename, modified)
+    }
+
+    pub(crate) fn add_file_from_reader<R: Read>(
+        &mut self,
+        mut reader: R,
+        filename: String,
+        modified: Option<SystemTime>,
+    ) -> ChestResult<()> {
+        validate_filename(&filename)?;
+        let mut cipher = Vec::new();
+        let size_bytes = reader.read_to_end(&mut cipher)? as u64;
         let metadata = Metadata {
-            filename: path
-                .as_ref()
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .into_owned(),
-            size_bytes: metadata.len(),
+            filename,
+            size_bytes,
+            modified: to_secs(modified),
+            sha256: Some(sha256(&cipher)),
+            compression: None,
         };
+        self.add_file_from_cipher(cipher, metadata)?;
+        Ok(())
+    }
+
+    /// Start a new snapshot, turning the files of a chest without snapshots
+    /// into its first one
+    pub(crate) fn start_snapshot(&mut self) -> Snapshot {
+        if self.snapshots.is_empty() && !self.files.is_empty() {
+            self.snapshots.push(Snapshot {
+                created: now(),
+                files: (0..self.files.len()).collect(),
+            });
+        }
+        Snapshot::new()
+    }
+
+    /// Add a file to the snapshot, sharing it with the latest snapshot when
+    /// its content didn't change, return whether it was stored
+    pub(crate) fn add_snapshot_file<R: Read>(
+        &mut self,
+        snapshot: &mut Snapshot,
+        mut reader: R,
+        filename: String,
+        modified: Option<SystemTime>,
+    ) -> ChestResult<bool> {
+        validate_filename(&filename)?;
         let mut cipher = Vec::new();
-        file.read_to_end(&mut cipher)?;
+        let size_bytes = reader.read_to_end(&mut cipher)? as u64;
+        let sha256 = sha256(&cipher);
+        let unchanged = self.snapshots.last().and_then(|latest| {
+            latest.files.iter().copied().find(|i| {
+                let metadata = &self.files[*i].metadata;
+                metadata.filename == filename && metadata.sha256 == Some(sha256)
+            })
+        });
+        if let Some(index) = unchanged {
+            snapshot.files.push(index);
+            return Ok(false);
+        }
+        let metadata = Metadata {
+            filename,
+            size_bytes,
+            modified: to_secs(modified),
+            sha256: Some(sha256),
+            compression: None,
+        };
         self.add_file_from_cipher(cipher, metadata)?;
+        snapshot.files.push(self.files.len() - 1);
+        Ok(true)
+    }
+
+    pub(crate) fn push_snapshot(&mut self, snapshot: Snapshot) {
+        self.snapshots.push(snapshot);
+    }
+
+    /// Remove a file from a chest without snapshots, its content being
+    /// dropped when the chest is locked
+    pub(crate) fn remove_file(&mut self, filename: &str) -> ChestResult<()> {
+        let index = self
+            .files
+            .iter()
+            .position(|f| f.metadata.filename == filename)
+            .ok_or_else(|| ChestError::FileNotFound(filename.to_string()))?;
+        let file = self.files.remove(index);
+        if let Content::Solid(offset) = file.content {
+            self.solid_plaintext()?;
+            let stream = self
+                .solid_plaintext
+                .get_mut()
+                .expect("Solid stream is loaded");
+            let length = file.metadata.size_bytes;
+            stream.drain(offset as usize..(offset + length) as usize);
+            self.files.iter_mut().for_each(|f| {
+                if let Content::Solid(other) = &mut f.content {
+                    if *other > offset {
+                        *other -= length;
+                    }
+                }
+            });
+        }
         Ok(())
     }
 
-    pub(crate) fn lock(self, password: &str) -> ChestResult<LockedChest> {
+    /// Files of the given snapshot, numbered from 1, or of the latest one
+    pub(crate) fn snapshot_files(
+        &self,
+        snapshot: Option<usize>,
+    ) -> ChestResult<Vec<&UnlockedFile>> {
+        let files = match (snapshot, self.snapshots.last()) {
+            (None, None) => return Ok(self.files.iter().collect()),
+            (None, Some(latest)) => latest,
+            (Some(number), _) => number
+                .checked_sub(1)
+                .and_then(|i| self.snapshots.get(i))
+                .ok_or(ChestError::SnapshotNotFound(number))?,
+        };
+        // indices of files lost while salvaging are skipped
+        Ok(files
+            .files
+            .iter()
+            .filter_map(|i| self.files.get(*i))
+            .collect())
+    }
+
+    pub(crate) fn lock(mut self, password: &str) -> ChestResult<LockedChest> {
+        if self.hidden {
+            return Err(ChestError::Hidden(
+                "hidden files can only be changed with chest hide",
+            ));
+        }
+        let hashes = self
+            .files
+            .iter()
+            .map(|f| self.content_sha256(f))
+            .collect::<ChestResult<Vec<_>>>()?;
+        self.files
+            .iter_mut()
+            .zip(hashes)
+            .for_each(|(f, sha256)| f.metadata.sha256 = Some(sha256));
+        let default_compression = self.default_compression();
+        let padding = self.padding;
         let public = self.public;
         let deriver = get_deriver(&public.key_derivation_algorithm);
         let encryptor = get_encryptor(&public.encryption_algorithm);
         let key = deriver.derive(password, &public.key_derivation_salt);
+        let dictionary = self
+            .dictionary
+            .map(|d| encryptor.encrypt(d, &key.clone().try_into().unwrap()))
+            .transpose()?;
+        let snapshots = match self.snapshots.is_empty() {
+            true => None,
+            false => Some(encryptor.encrypt(
+                bincode::serialize(&self.snapshots)?,
+                &key.clone().try_into().unwrap(),
+            )?),
+        };
+        let mut locked = LockedChest {
+            format: FORMAT_VERSION,
+            public,
+            index: None,
+            hidden_index: None,
+            files: Vec::default(),
+            dictionary,
+            solid: None,
+            chunks: BTreeMap::default(),
+            snapshots,
+            redundancy: self.redundancy,
+            legacy: false,
+        };
+        // metadata moved to the padded index instead of one blob per file
+        let mut padded_metadata = Vec::new();
+        if locked.public.layout == Layout::Solid {
+            let (compression, stream) = match (self.solid_plaintext.into_inner(), self.solid_stream)
+            {
+                // untouched since unlocked, no need to compress it again
+                (None, Some(stream)) => (self.solid_compression, stream),
+                (plaintext, _) => {
+                    let (compression, stream) = adaptive::compress(
+                        &[],
+                        default_compression,
+                        None,
+                        "",
+                        plaintext.unwrap_or_default(),
+                    )?;
+                    let stream = encryptor.encrypt(stream, &key.clone().try_into().unwrap())?;
+                    (compression, stream)
+                }
+            };
+            let index = SolidIndex {
+                compression,
+                files: self
+                    .files
+                    .into_iter()
+                    .filter_map(|f| match f.content {
+                        Content::Solid(offset) => Some((f.metadata, offset)),
+                        Content::Blob(_) | Content::Chunks(_) => None,
+                    })
+                    .collect(),
+            };
+            let index = encryptor.encrypt(
+                bincode::serialize(&index)?,
+                &key.clone().try_into().unwrap(),
+            )?;
+            locked.solid = Some(LockedSolid { index, stream });
+        } else {
+            let mut chunks = self.chunks;
+            let referenced = self
+                .files
+                .iter()
+                .filter_map(|f| match &f.content {
+                    Content::Chunks(chunks) => Some(chunks.iter().map(|c| c.id)),
+                    _ => None,
+                })
+                .flatten()
+                .collect::<HashSet<_>>();
+            chunks.retain(|id, _| referenced.contains(id));
+            locked.chunks = chunks;
+            locked.files = self
+                .files
+                .into_iter()
+                .map(|f| {
+                    let cipher = match f.content {
+                        Content::Blob(cipher) => cipher,
+                        Content::Chunks(chunks) => encryptor.encrypt(
+                            bincode::serialize(&chunks)?,
+                            &key.clone().try_into().unwrap(),
+                        )?,
+                        Content::Solid(_) => unreachable!("Solid files are only in solid chests"),
+                    };
+                    let metadata = match &padding {
+                        Some(_) => {
+                            padded_metadata.push(f.metadata);
+                            EncryptedBlob::default()
+                        }
+                        None => encryptor.encrypt(
+                            bincode::serialize(&f.metadata)?,
+                            &key.clone().try_into().unwrap(),
+                        )?,
+                    };
+                    Ok(LockedFile { cipher, metadata })
+                })
+                .collect::<Result<Vec<_>, ChestError>>()?;
+        }
+        if let Some(padding) = padding {
+            let hidden_index = padding.hidden_index.clone();
+            let decoys = padding.decoy_count();
+            let index = locked.pad(padding, padded_metadata)?;
+            let mut index = padding::seal_index(&encryptor, &key, bincode::serialize(&index)?)?;
+            // both index slots have the same size, whether files are hidden or not
+            let mut hidden_index = match hidden_index {
+                Some(hidden_index) => hidden_index,
+                None => padding::decoy(0)?,
+            };
+            let slot = padding::slot_len(index.cipher.len(), decoys);
+            if hidden_index.cipher.len() > slot {
+                return Err(padding::corrupted("hidden index is larger than its slot"));
+            }
+            padding::fill(&mut index, slot)?;
+            padding::fill(&mut hidden_index, slot)?;
+            locked.index = Some(index);
+            locked.hidden_index = Some(hidden_index);
+        }
+        Ok(locked)
+    }
+
+    /// Files of the snapshot matching the filter
+    pub(crate) fn matching_files(
+        &self,
+        filter: &FileFilter,
+        snapshot: Option<usize>,
+    ) -> ChestResult<Vec<&UnlockedFile>> {
         let files = self
-            .files
+            .snapshot_files(snapshot)?
             .into_iter()
-            .map(|f| {
-                Ok(LockedFile {
-                    cipher: f.cipher,
-                    metadata: encryptor.encrypt(
-                        bincode::serialize(&f.metadata)?,
-                        &key.clone().try_into().unwrap(),
-                    )?,
-                })
-            })
-            .collect::<Result<Vec<_>, ChestError>>()?;
-        Ok(LockedChest { public, files })
+            .filter(|f| filter.matches(&f.metadata.filename))
+            .collect::<Vec<_>>();
+        if files.is_empty() && !filter.is_empty() {
+            return Err(ChestError::NoMatchingFiles);
+        }
+        Ok(files)
     }
 
-    pub(crate) fn decrypt_files_to_folder<P: AsRef<Path>>(&self, path: P) -> ChestResult<()> {
-        fs::create_dir_all(&path)?;
-        let encryptor = get_encryptor(&self.public.encryption_algorithm);
-        let compressor = &self
-            .public
-            .compression_algorithm
+    /// Decrypt each file to its path, file names must have been validated
+    pub(crate) fn decrypt_files_to_paths(
+        &self,
+        files: &[(&UnlockedFile, PathBuf)],
+    ) -> ChestResult<()> {
+        files.iter().try_for_each(|(f, path)| {
+            let mut file = conflict::create(path)?;
+            self.decrypt_file_to_writer(f, &mut file)?;
+            Ok(())
+        })
+    }
+
+    pub(crate) fn ensure_supported(&self) -> ChestResult<()> {
+        self.files
+            .iter()
+            .filter_map(|f| f.metadata.compression.as_ref())
+            .chain(self.solid_compression.as_ref())
+            .try_for_each(|c| get_file_compressor(c, self.dictionary.as_deref()).map(|_| ()))?;
+        Ok(())
+    }
+
+    pub(crate) fn dedup_stats(&self) -> Option<dedup::DedupStats> {
+        (self.public.layout == Layout::Dedup).then(|| dedup::stats(&self.files))
+    }
+
+    /// Hash of the file content, decrypting first format files whose hash
+    /// isn't recorded
+    pub(crate) fn content_sha256(&self, file: &UnlockedFile) -> ChestResult<[u8; 32]> {
+        if let Some(sha256) = file.metadata.sha256 {
+            return Ok(sha256);
+        }
+        let mut content = Vec::new();
+        self.decrypt_file_to_writer(file, &mut content)?;
+        Ok(sha256(&content))
+    }
+
+    pub(crate) fn find_file(&self, filename: &str) -> ChestResult<&UnlockedFile> {
+        self.snapshot_files(None)?
+            .into_iter()
+            .find(|f| f.metadata.filename == filename)
+            .ok_or_else(|| ChestError::FileNotFound(filename.to_string()))
+    }
+
+    /// Stored size of the whole chest content
+    pub(crate) fn stored_bytes(&self) -> u64 {
+        let files = self
+            .files
+            .iter()
+            .filter_map(|f| f.stored_bytes())
+            .sum::<u64>();
+        let stream = self
+            .solid_stream
             .as_ref()
-            .map(get_compressor);
-        self.files.iter().try_for_each::<_, ChestResult<()>>(|f| {
-            let binary = encryptor.decrypt(&f.cipher, &self.key.clone().try_into().unwrap())?;
-            let binary = match compressor {
-                Some(compressor) => compressor.decompress(&binary)?,
-                None => binary,
+            .map_or(0, |s| s.cipher.len() as u64);
+        let chunks = self
+            .chunks
+            .values()
+            .map(|c| c.cipher.len() as u64)
+            .sum::<u64>();
+        files + stream + chunks
+    }
+
+    pub(crate) fn decrypt_file_to_writer<W: Write>(
+        &self,
+        file: &UnlockedFile,
+        writer: &mut W,
+    ) -> ChestResult<u64> {
+        let blob = match &file.content {
+            Content::Blob(blob) => blob,
+            Content::Chunks(chunks) => return self.decrypt_chunks_to_writer(chunks, writer),
+            Content::Solid(offset) => {
+                let stream = self.solid_plaintext()?;
+                let start = *offset as usize;
+                let end = start + file.metadata.size_bytes as usize;
+                let binary = stream
+                    .get(start..end)
+                    .ok_or_else(|| ChestError::FileNotFound(file.metadata.filename.clone()))?;
+                writer.write_all(binary)?;
+                writer.flush()?;
+                return Ok(binary.len() as u64);
+            }
+        };
+        let encryptor = get_encryptor(&self.public.encryption_algorithm);
+        let binary = encryptor.decrypt(blob, &self.key.clone().try_into().unwrap())?;
+        let written = match &file.metadata.compression {
+            Some(compression) => {
+                let compressor = get_file_compressor(compression, self.dictionary.as_deref())?;
+                compressor.decompress_to(&binary, writer)?
+            }
+            None => {
+                writer.write_all(&binary)?;
+                binary.len() as u64
+            }
+        };
+        writer.flush()?;
+        Ok(written)
+    }
+
+    fn decrypt_chunks_to_writer<W: Write>(
+        &self,
+        chunks: &[ChunkRef],
+        writer: &mut W,
+    ) -> ChestResult<u64> {
+        let encryptor = get_encryptor(&self.public.encryption_algorithm);
+        let compression = self.default_compression();
+        let mut written = 0;
+        chunks.iter().try_for_each::<_, ChestResult<()>>(|chunk| {
+            let blob = self.chunks.get(&chunk.id).ok_or(ChestError::MissingChunk)?;
+            let binary = encryptor.decrypt(blob, &self.key.clone().try_into().unwrap())?;
+            let (flag, binary) = binary.split_first().ok_or(ChestError::MissingChunk)?;
+            written += match (*flag, &compression) {
+                (CHUNK_COMPRESSED, Some(compression)) => {
+                    get_file_compressor(compression, None)?.decompress_to(binary, writer)?
+                }
+                _ => {
+                    writer.write_all(binary)?;
+                    binary.len() as u64
+                }
             };
-            let file_path = path.as_ref().join(&f.metadata.filename);
-            let mut file = fs::File::create(file_path)?;
-            file.write_all(&binary)?;
             Ok(())
         })?;
-        Ok(())
+        writer.flush()?;
+        Ok(written)
+    }
+
+    fn solid_plaintext(&self) -> ChestResult<&Vec<u8>> {
+        self.solid_plaintext.get_or_try_init(|| {
+            let Some(stream) = &self.solid_stream else {
+                return Ok(Vec::new());
+            };
+            let encryptor = get_encryptor(&self.public.encryption_algorithm);
+            let binary = encryptor.decrypt(stream, &self.key.clone().try_into().unwrap())?;
+            match &self.solid_compression {
+                Some(compression) => {
+                    let mut plaintext = Vec::new();
+                    get_file_compressor(compression, None)?
+                        .decompress_to(&binary, &mut plaintext)?;
+                    Ok(plaintext)
+                }
+                None => Ok(binary),
+            }
+        })
     }
 }
 
@@ -173,33 +879,788 @@ impl LockedChest {
         let mut file = fs::File::open(path)?;
         let mut payload = Vec::new();
         file.read_to_end(&mut payload)?;
-        Ok(bincode::deserialize(&payload)?)
+        let checked = container::check(&payload)?;
+        if !checked.damage.is_empty() {
+            return Err(ChestError::Damaged(checked.damage));
+        }
+        let mut chest = Self::from_body(checked.body)?;
+        chest.redundancy = checked.redundancy;
+        Ok(chest)
+    }
+
+    /// Chest serialized in the current or the first format
+    pub(crate) fn from_body(body: &[u8]) -> ChestResult<Self> {
+        if let Some(chest) = Self::first_format(body) {
+            return Ok(chest);
+        }
+        check_format(body)?;
+        Ok(bincode::deserialize(body)?)
+    }
+
+    /// Chest written in the first format, its metadata being converted when
+    /// unlocked
+    fn first_format(body: &[u8]) -> Option<Self> {
+        let (public, files) = legacy::read(body)?;
+        Some(LockedChest {
+            format: FORMAT_VERSION,
+            public,
+            index: None,
+            hidden_index: None,
+            files,
+            dictionary: None,
+            solid: None,
+            chunks: BTreeMap::default(),
+            snapshots: None,
+            redundancy: None,
+            legacy: true,
+        })
     }
 
-    pub(crate) fn write_to_file<P: AsRef<Path>>(&self, path: P) -> ChestResult<()> {
-        let serialized = bincode::serialize(self)?;
-        let mut file = fs::File::create(path)?;
-        file.write_all(&serialized)?;
+    /// Replace the file atomically, keeping the previous version as
+    /// `name.chest~` if `backup` is set
+    pub(crate) fn write_to_file<P: AsRef<Path>>(&self, path: P, backup: bool) -> ChestResult<()> {
+        let serialized = container::wrap(&bincode::serialize(self)?, self.redundancy)?;
+        atomic::write(path.as_ref(), &serialized, backup)?;
         Ok(())
     }
 
+    /// Read as much of a possibly truncated or damaged serialized chest as
+    /// possible, with a description of what couldn't be read
+    pub(crate) fn from_partial(body: &[u8]) -> ChestResult<(Self, Option<String>)> {
+        if let Some(chest) = Self::first_format(body) {
+            return Ok((chest, None));
+        }
+        check_format(body)?;
+        let mut reader = body;
+        let mut chest = LockedChest {
+            format: bincode::deserialize_from(&mut reader)?,
+            public: bincode::deserialize_from(&mut reader)?,
+            index: None,
+            hidden_index: None,
+            files: Vec::default(),
+            dictionary: None,
+            solid: None,
+            chunks: BTreeMap::default(),
+            snapshots: None,
+            redundancy: None,
+            legacy: false,
+        };
+        // fields are read one by one, bincode having no framing between them
+        let result = (|| -> bincode::Result<()> {
+            chest.index = bincode::deserialize_from(&mut reader)?;
+            chest.hidden_index = bincode::deserialize_from(&mut reader)?;
+            let files: u64 = bincode::deserialize_from(&mut reader)?;
+            for _ in 0..files {
+                chest.files.push(bincode::deserialize_from(&mut reader)?);
+            }
+            chest.dictionary = bincode::deserialize_from(&mut reader)?;
+            chest.solid = bincode::deserialize_from(&mut reader)?;
+            let chunks: u64 = bincode::deserialize_from(&mut reader)?;
+            for _ in 0..chunks {
+                let (id, chunk) = bincode::deserialize_from(&mut reader)?;
+                chest.chunks.insert(id, chunk);
+            }
+            chest.snapshots = bincode::deserialize_from(&mut reader)?;
+            Ok(())
+        })();
+        let problem = result.err().map(|e| {
+            format!(
+                "Chest is cut short after {} file(s), the rest couldn't be read: {e}",
+                chest.files.len()
+            )
+        });
+        Ok((chest, problem))
+    }
+
     pub(crate) fn unlock(self, password: &str) -> ChestResult<UnlockedChest> {
-        let public = self.public;
-        let deriver = get_deriver(&public.key_derivation_algorithm);
-        let encryptor = get_encryptor(&public.encryption_algorithm);
-        let key = deriver.derive(password, &public.key_derivation_salt);
-        let files = self
+        self.unlock_with(password, None)
+    }
+
+    /// Unlock the chest skipping what can't be decrypted, described in the
+    /// returned list, failing only if no file at all can be decrypted
+    pub(crate) fn unlock_salvaging(
+        self,
+        password: &str,
+    ) -> ChestResult<(UnlockedChest, Vec<String>)> {
+        let mut lost = Vec::new();
+        let chest = self.unlock_with(password, Some(&mut lost))?;
+        Ok((chest, lost))
+    }
+
+    /// Every blob of the chest, in the order their lengths are kept in the
+    /// padded index
+    fn blobs_mut(&mut self) -> impl Iterator<Item = &mut EncryptedBlob> {
+        self.files
+            .iter_mut()
+            .map(|f| &mut f.cipher)
+            .chain(self.dictionary.iter_mut())
+            .chain(
+                self.solid
+                    .iter_mut()
+                    .flat_map(|s| [&mut s.index, &mut s.stream]),
+            )
+            .chain(self.chunks.values_mut())
+            .chain(self.snapshots.iter_mut())
+    }
+
+    /// Pad every blob to its Padmé length then add the decoy files, new ones
+    /// sized between the smallest and largest real file blobs, returning the
+    /// index to decrypt the chest
+    fn pad(&mut self, padding: Padding, metadata: Vec<Metadata>) -> ChestResult<PaddedIndex> {
+        // every file of a solid chest would be a decoy
+        if self.public.layout == Layout::Solid && padding.decoy_count() > 0 {
+            return Err(ChestError::Hidden(
+                "solid chests can't have decoys, their files are in a single stream",
+            ));
+        }
+        let lengths = self
+            .blobs_mut()
+            .map(padding::pad)
+            .collect::<ChestResult<Vec<_>>>()?;
+        // chunks, dictionary, solid stream and snapshots don't look like files
+        let sizes = self
+            .files
+            .iter()
+            .map(|f| f.cipher.cipher.len())
+            .collect::<Vec<_>>();
+        let min = sizes.iter().copied().min().unwrap_or_default();
+        let max = sizes.iter().copied().max().unwrap_or_default();
+        let mut decoys = padding.decoys;
+        for _ in 0..padding.new_decoys {
+            decoys.push(padding::decoy(padding::random_size(min, max)?)?);
+        }
+        self.files
+            .extend(decoys.into_iter().map(|cipher| LockedFile {
+                cipher,
+                metadata: EncryptedBlob::default(),
+            }));
+        Ok(PaddedIndex { metadata, lengths })
+    }
+
+    /// Decrypt the padded index, drop the decoy files and restore the blobs
+    /// length, returning the real files metadata and the padding used
+    fn unpad(
+        &mut self,
+        encryptor: &impl Encrypt,
+        key: &[u8],
+    ) -> ChestResult<Option<(Vec<Metadata>, Padding)>> {
+        let Some(slot) = &self.index else {
+            return Ok(None);
+        };
+        let index: PaddedIndex = bincode::deserialize(&padding::open_index(encryptor, key, slot)?)?;
+        let decoys = self
             .files
+            .split_off(index.metadata.len().min(self.files.len()))
             .into_iter()
-            .map(|f| {
+            .map(|f| f.cipher)
+            .collect();
+        let padding = Padding {
+            decoys,
+            new_decoys: 0,
+            hidden_index: self.hidden_index.take(),
+        };
+        self.index = None;
+        self.blobs_mut()
+            .zip(&index.lengths)
+            .try_for_each(|(blob, len)| padding::unpad(blob, *len))?;
+        Ok(Some((index.metadata, padding)))
+    }
+
+    /// Chest of the files hidden in the decoys, found by decrypting the
+    /// hidden index slot with the key of the second password
+    fn unlock_hidden(self, index: &[u8], key: Vec<u8>) -> ChestResult<UnlockedChest> {
+        let index: HiddenIndex = bincode::deserialize(index)?;
+        let count = self.files.len();
+        let files = index
+            .metadata
+            .into_iter()
+            .zip(index.from_end)
+            .zip(index.lengths)
+            .map(|((metadata, from_end), len)| {
+                let mut blob = count
+                    .checked_sub(from_end)
+                    .and_then(|i| self.files.get(i))
+                    .ok_or_else(|| padding::corrupted("hidden file is outside of the chest"))?
+                    .cipher
+                    .clone();
+                padding::unpad(&mut blob, len)?;
                 Ok(UnlockedFile {
-                    cipher: f.cipher,
-                    metadata: bincode::deserialize(
+                    content: Content::Blob(blob),
+                    metadata,
+                })
+            })
+            .collect::<ChestResult<Vec<_>>>()?;
+        Ok(UnlockedChest {
+            key,
+            public: Public {
+                layout: Layout::Blobs,
+                ..self.public
+            },
+            files,
+            dictionary: None,
+            solid_stream: None,
+            solid_compression: None,
+            chunks: BTreeMap::default(),
+            snapshots: Vec::default(),
+            redundancy: self.redundancy,
+            padding: None,
+            hidden: true,
+            solid_plaintext: OnceCell::new(),
+            compression_rules: Vec::default(),
+        })
+    }
+
+    fn unlock_with(
+        mut self,
+        password: &str,
+        mut lost: Option<&mut Vec<String>>,
+    ) -> ChestResult<UnlockedChest> {
+        let deriver = get_deriver(&self.public.key_derivation_algorithm);
+        let encryptor = get_encryptor(&self.public.encryption_algorithm);
+        let key = deriver.derive(password, &self.public.key_derivation_salt);
+        let (mut padded_metadata, padding) = match self.unpad(&encryptor, &key) {
+            Ok(Some((metadata, padding))) => (Some(metadata.into_iter()), Some(padding)),
+            Ok(None) => (None, None),
+            // the password may open the files hidden in the decoys instead
+            Err(e) => {
+                let hidden = self
+                    .hidden_index
+                    .as_ref()
+                    .map(|slot| padding::open_index(&encryptor, &key, slot));
+                return match hidden {
+                    Some(Ok(index)) => self.unlock_hidden(&index, key),
+                    _ => Err(e),
+                };
+            }
+        };
+        let public = self.public;
+        let count = self.files.len();
+        let mut first_error = None;
+        let mut files = Vec::with_capacity(count);
+        for (i, f) in self.files.into_iter().enumerate() {
+            let file = (|| -> ChestResult<UnlockedFile> {
+                let metadata = match padded_metadata.as_mut().and_then(Iterator::next) {
+                    Some(metadata) => metadata,
+                    None if self.legacy => legacy::metadata(
                         &encryptor.decrypt(&f.metadata, &key.clone().try_into().unwrap())?,
+                        &public,
                     )?,
-                })
+                    None => bincode::deserialize(
+                        &encryptor.decrypt(&f.metadata, &key.clone().try_into().unwrap())?,
+                    )?,
+                };
+                let content = match public.layout {
+                    Layout::Dedup => Content::Chunks(bincode::deserialize(
+                        &encryptor.decrypt(&f.cipher, &key.clone().try_into().unwrap())?,
+                    )?),
+                    _ => Content::Blob(f.cipher),
+                };
+                Ok(UnlockedFile { content, metadata })
+            })();
+            let what = format!("File #{}", i + 1);
+            files.extend(salvaged(file, &mut lost, &mut first_error, &what)?);
+        }
+        let dictionary = self
+            .dictionary
+            .map(|d| encryptor.decrypt(&d, &key.clone().try_into().unwrap()))
+            .transpose();
+        let dictionary = salvaged(
+            dictionary.map_err(Into::into),
+            &mut lost,
+            &mut first_error,
+            "Compression dictionary",
+        )?
+        .flatten();
+        let (solid_stream, solid_compression) = match self.solid {
+            Some(solid) => {
+                let index = encryptor
+                    .decrypt(&solid.index, &key.clone().try_into().unwrap())
+                    .map_err(ChestError::from)
+                    .and_then(|index| Ok(bincode::deserialize::<SolidIndex>(&index)?));
+                match salvaged(index, &mut lost, &mut first_error, "Solid stream index")? {
+                    Some(index) => {
+                        files.extend(index.files.into_iter().map(|(metadata, offset)| {
+                            UnlockedFile {
+                                content: Content::Solid(offset),
+                                metadata,
+                            }
+                        }));
+                        (Some(solid.stream), index.compression)
+                    }
+                    None => (None, None),
+                }
+            }
+            None => (None, None),
+        };
+        let snapshots = self
+            .snapshots
+            .map(|s| -> ChestResult<Vec<Snapshot>> {
+                Ok(bincode::deserialize(
+                    &encryptor.decrypt(&s, &key.clone().try_into().unwrap())?,
+                )?)
             })
-            .collect::<Result<Vec<_>, ChestError>>()?;
-        Ok(UnlockedChest { key, public, files })
+            .transpose();
+        // nothing decrypts with a wrong password
+        if let Some(e) = first_error.filter(|_| files.is_empty()) {
+            return Err(e);
+        }
+        let mut snapshots = salvaged(snapshots, &mut lost, &mut None, "Snapshots")?
+            .flatten()
+            .unwrap_or_default();
+        // snapshot file indices are wrong once files are missing
+        if let Some(lost) = lost.filter(|_| files.len() != count && !snapshots.is_empty()) {
+            lost.push("Snapshots, every stored version of the files is kept".to_string());
+            snapshots.clear();
+        }
+        Ok(UnlockedChest {
+            key,
+            public,
+            files,
+            dictionary,
+            solid_stream,
+            solid_compression,
+            chunks: self.chunks,
+            snapshots,
+            redundancy: self.redundancy,
+            padding,
+            hidden: false,
+            solid_plaintext: OnceCell::new(),
+            compression_rules: Vec::default(),
+        })
+    }
+}
+
+/// Record the error and carry on when salvaging, propagate it otherwise
+fn salvaged<T>(
+    result: ChestResult<T>,
+    lost: &mut Option<&mut Vec<String>>,
+    first_error: &mut Option<ChestError>,
+    what: &str,
+) -> ChestResult<Option<T>> {
+    match (result, lost) {
+        (Ok(value), _) => Ok(Some(value)),
+        (Err(e), Some(lost)) => {
+            lost.push(format!("{what}: {e}"));
+            first_error.get_or_insert(e);
+            Ok(None)
+        }
+        (Err(e), None) => Err(e),
+    }
+}
+
+pub(crate) fn sha256(payload: &[u8]) -> [u8; 32] {
+    digest::digest(&digest::SHA256, payload)
+        .as_ref()
+        .try_into()
+        .expect("SHA-256 digests are 32 bytes")
+}
+
+fn to_secs(time: Option<SystemTime>) -> Option<u64> {
+    time.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+fn now() -> u64 {
+    to_secs(Some(SystemTime::now())).unwrap_or_default()
+}
+
+/// Reject payloads written by newer chests, anything else being left to
+/// bincode to report
+fn check_format(body: &[u8]) -> ChestResult<()> {
+    if let [low, high, ..] = body {
+        let format = u16::from_le_bytes([*low, *high]);
+        if format > FORMAT_VERSION {
+            return Err(ChestError::UnsupportedVersion(format));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_filename(filename: &str) -> ChestResult<()> {
+    let path = Path::new(filename);
+    // a trailing separator or a path of only `.` names a directory
+    let is_valid = !filename.ends_with(std::path::is_separator)
+        && path.components().any(|c| matches!(c, Component::Normal(_)))
+        && path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !is_valid {
+        return Err(ChestError::InvalidFilename(filename.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "password";
+
+    #[test]
+    fn size_is_counted_while_reading() {
+        let mut chest = UnlockedChest::new(
+            PASSWORD,
+            Some(CompressionAlgorithm::Zstd),
+            None,
+            Layout::Blobs,
+        )
+        .unwrap();
+        chest
+            .add_file_from_reader(&b"some data"[..], "data.txt".to_string(), None)
+            .unwrap();
+        assert_eq!(9, chest.files[0].metadata.size_bytes);
+        assert_eq!(Some(sha256(b"some data")), chest.files[0].metadata.sha256);
+    }
+
+    #[test]
+    fn first_format_chests_are_migrated() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/baseline.chest");
+        let chest = LockedChest::from_file(path)
+            .unwrap()
+            .unlock("baseline password")
+            .unwrap();
+        let content = b"written by the first chest\n";
+        let file = chest.find_file("notes.txt").unwrap();
+        assert_eq!(content.len() as u64, file.metadata.size_bytes);
+        assert_eq!(None, file.metadata.sha256);
+        assert_eq!(sha256(content), chest.content_sha256(file).unwrap());
+        let chest = chest
+            .lock("baseline password")
+            .unwrap()
+            .unlock("baseline password")
+            .unwrap();
+        let file = chest.find_file("notes.txt").unwrap();
+        assert_eq!(Some(sha256(content)), file.metadata.sha256);
+        let mut read = Vec::new();
+        chest.decrypt_file_to_writer(file, &mut read).unwrap();
+        assert_eq!(content.to_vec(), read);
+    }
+
+    #[test]
+    fn first_format_chests_pass_check() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/baseline.chest");
+        let payload = fs::read(path).unwrap();
+        let checked = container::check(&payload).unwrap();
+        assert_eq!(None, checked.blocks);
+        assert!(checked.damage.is_empty());
+        assert!(LockedChest::from_body(checked.body).is_ok());
+    }
+
+    #[test]
+    fn newer_formats_are_rejected() {
+        let chest = UnlockedChest::new(PASSWORD, None, None, Layout::Blobs).unwrap();
+        let mut payload = bincode::serialize(&chest.lock(PASSWORD).unwrap()).unwrap();
+        assert!(LockedChest::from_body(&payload).is_ok());
+        payload[0] = FORMAT_VERSION as u8 + 1;
+        assert!(matches!(
+            LockedChest::from_body(&payload),
+            Err(ChestError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn solid_files_are_read_back_after_locking() {
+        let mut chest = UnlockedChest::new(
+            PASSWORD,
+            Some(CompressionAlgorithm::Zstd),
+            None,
+            Layout::Solid,
+        )
+        .unwrap();
+        chest
+            .add_file_from_reader(&b"first file"[..], "first.txt".to_string(), None)
+            .unwrap();
+        chest
+            .add_file_from_reader(&b"second file"[..], "second.txt".to_string(), None)
+            .unwrap();
+        let chest = chest.lock(PASSWORD).unwrap().unlock(PASSWORD).unwrap();
+        let mut second = Vec::new();
+        chest
+            .decrypt_file_to_writer(chest.find_file("second.txt").unwrap(), &mut second)
+            .unwrap();
+        assert_eq!(b"second file".to_vec(), second);
+        assert_eq!(None, chest.files[0].stored_bytes());
+    }
+
+    #[test]
+    fn padded_chests_hide_sizes_and_are_read_back() {
+        [Layout::Blobs, Layout::Solid, Layout::Dedup]
+            .into_iter()
+            .for_each(|layout| {
+                let mut chest =
+                    UnlockedChest::new(PASSWORD, Some(CompressionAlgorithm::Zstd), None, layout)
+                        .unwrap();
+                let new_decoys = match layout {
+                    Layout::Solid => 0,
+                    _ => 3,
+                };
+                chest.padding = Some(Padding {
+                    new_decoys,
+                    ..Padding::default()
+                });
+                let payload = crate::random::generate_random_bytes(1000).unwrap();
+                chest
+                    .add_file_from_reader(&payload[..], "random.bin".to_string(), None)
+                    .unwrap();
+                chest
+                    .add_file_from_reader(&b"small file"[..], "small.txt".to_string(), None)
+                    .unwrap();
+                let mut locked = chest.lock(PASSWORD).unwrap();
+                assert!(locked
+                    .blobs_mut()
+                    .all(|b| b.cipher.len() as u64 == padding::padme(b.cipher.len() as u64)));
+                assert!(locked.files.iter().all(|f| f.metadata.cipher.is_empty()));
+                // decoys are sized like the real file blobs of the layout
+                let (real, decoys) = locked.files.split_at(locked.files.len() - new_decoys);
+                let sizes = real.iter().map(|f| f.cipher.cipher.len());
+                let range =
+                    sizes.clone().min().unwrap_or_default()..=sizes.max().unwrap_or_default();
+                assert!(decoys
+                    .iter()
+                    .all(|f| range.contains(&f.cipher.cipher.len())));
+                let chest = locked.unlock(PASSWORD).unwrap();
+                assert_eq!(
+                    Some(new_decoys),
+                    chest.padding.as_ref().map(Padding::decoy_count)
+                );
+                assert_eq!(2, chest.files.len());
+                let mut random = Vec::new();
+                chest
+                    .decrypt_file_to_writer(chest.find_file("random.bin").unwrap(), &mut random)
+                    .unwrap();
+                assert_eq!(payload, random);
+            });
+        let mut solid = UnlockedChest::new(PASSWORD, None, None, Layout::Solid).unwrap();
+        solid.padding = Some(Padding {
+            new_decoys: 1,
+            ..Padding::default()
+        });
+        assert!(matches!(solid.lock(PASSWORD), Err(ChestError::Hidden(_))));
+    }
+
+    #[test]
+    fn hidden_files_open_with_the_second_password() {
+        let mut chest = UnlockedChest::new(PASSWORD, None, None, Layout::Blobs).unwrap();
+        chest.padding = Some(Padding {
+            new_decoys: 4,
+            ..Padding::default()
+        });
+        let decoy = crate::random::generate_random_bytes(5000).unwrap();
+        chest
+            .add_file_from_reader(&decoy[..], "decoy.bin".to_string(), None)
+            .unwrap();
+        chest
+            .add_file_from_reader(&decoy[..100], "small.bin".to_string(), None)
+            .unwrap();
+        let mut chest = chest.lock(PASSWORD).unwrap().unlock(PASSWORD).unwrap();
+        assert!(chest.new_hidden(PASSWORD).is_err());
+        let mut hidden = chest.new_hidden("hidden").unwrap();
+        hidden
+            .add_file_from_reader(&b"secret"[..], "secret.txt".to_string(), None)
+            .unwrap();
+        chest.hide(hidden).unwrap();
+        let locked = chest.lock(PASSWORD).unwrap();
+        let index = locked.index.as_ref().unwrap();
+        let hidden_index = locked.hidden_index.as_ref().unwrap();
+        assert_eq!(index.cipher.len(), hidden_index.cipher.len());
+        let payload = bincode::serialize(&locked).unwrap();
+        let locked: LockedChest = bincode::deserialize(&payload).unwrap();
+        let hidden = locked.unlock("hidden").unwrap();
+        assert!(hidden.hidden);
+        let mut secret = Vec::new();
+        hidden
+            .decrypt_file_to_writer(hidden.find_file("secret.txt").unwrap(), &mut secret)
+            .unwrap();
+        assert_eq!(b"secret", &secret[..]);
+        assert!(hidden.lock("hidden").is_err());
+
+        let locked: LockedChest = bincode::deserialize(&payload).unwrap();
+        let chest = locked.unlock(PASSWORD).unwrap();
+        assert_eq!(2, chest.files.len());
+        // hidden files survive the chest being locked again
+        let locked = chest.lock(PASSWORD).unwrap();
+        assert_eq!(1, locked.unlock("hidden").unwrap().files.len());
+        let locked: LockedChest = bincode::deserialize(&payload).unwrap();
+        assert!(locked.unlock("wrong").is_err());
+    }
+
+    #[test]
+    fn hidden_files_dont_change_the_index_slots() {
+        let locked = |hide: bool| {
+            let mut chest = UnlockedChest::new(PASSWORD, None, None, Layout::Blobs).unwrap();
+            chest.padding = Some(Padding {
+                new_decoys: 8,
+                ..Padding::default()
+            });
+            chest
+                .add_file_from_reader(&[0; 5000][..], "large.bin".to_string(), None)
+                .unwrap();
+            let mut chest = chest.lock(PASSWORD).unwrap().unlock(PASSWORD).unwrap();
+            if hide {
+                let mut hidden = chest.new_hidden("hidden").unwrap();
+                (0..8).for_each(|i| {
+                    let name = format!("{i}{}.txt", "long name ".repeat(15));
+                    hidden.add_file_from_reader(&b"x"[..], name, None).unwrap();
+                });
+                chest.hide(hidden).unwrap();
+            }
+            chest.lock(PASSWORD).unwrap()
+        };
+        let (plain, hiding) = (locked(false), locked(true));
+        let slot = |locked: &LockedChest| locked.index.as_ref().unwrap().cipher.len();
+        assert_eq!(slot(&plain), slot(&hiding));
+        assert_eq!(
+            plain.hidden_index.as_ref().unwrap().cipher.len(),
+            hiding.hidden_index.as_ref().unwrap().cipher.len()
+        );
+        // the length the index was sealed at, learned from the first password
+        let sealed = |locked: &LockedChest| {
+            let key = get_deriver(&locked.public.key_derivation_algorithm)
+                .derive(PASSWORD, &locked.public.key_derivation_salt);
+            let encryptor = get_encryptor(&locked.public.encryption_algorithm);
+            padding::open_index(&encryptor, &key, locked.index.as_ref().unwrap())
+                .unwrap()
+                .len()
+        };
+        assert_eq!(sealed(&plain), sealed(&hiding));
+        assert_eq!(8, hiding.unlock("hidden").unwrap().files.len());
+    }
+
+    #[test]
+    fn dedup_chunks_are_shared_and_read_back() {
+        let mut chest = UnlockedChest::new(
+            PASSWORD,
+            Some(CompressionAlgorithm::Zstd),
+            None,
+            Layout::Dedup,
+        )
+        .unwrap();
+        let payload = crate::random::generate_random_bytes(512 * 1024).unwrap();
+        chest
+            .add_file_from_reader(&payload[..], "first.bin".to_string(), None)
+            .unwrap();
+        chest
+            .add_file_from_reader(&payload[..], "second.bin".to_string(), None)
+            .unwrap();
+        let chest = chest.lock(PASSWORD).unwrap().unlock(PASSWORD).unwrap();
+        let stats = chest.dedup_stats().unwrap();
+        assert_eq!(stats.chunks, stats.unique_chunks * 2);
+        assert_eq!(payload.len() as u64, stats.saved_bytes());
+        let mut second = Vec::new();
+        chest
+            .decrypt_file_to_writer(chest.find_file("second.bin").unwrap(), &mut second)
+            .unwrap();
+        assert_eq!(payload, second);
+    }
+
+    #[test]
+    fn unchanged_files_are_shared_between_snapshots() {
+        let mut chest = UnlockedChest::new(
+            PASSWORD,
+            Some(CompressionAlgorithm::Zstd),
+            None,
+            Layout::Blobs,
+        )
+        .unwrap();
+        let versions: [&[(&str, &[u8])]; 2] = [
+            &[("a.conf", b"first"), ("b.conf", b"same")],
+            &[("b.conf", b"same"), ("c.conf", b"new")],
+        ];
+        versions.iter().for_each(|files| {
+            let mut snapshot = chest.start_snapshot();
+            files.iter().for_each(|(name, content)| {
+                chest
+                    .add_snapshot_file(&mut snapshot, *content, name.to_string(), None)
+                    .unwrap();
+            });
+            chest.push_snapshot(snapshot);
+        });
+        let chest = chest.lock(PASSWORD).unwrap().unlock(PASSWORD).unwrap();
+        assert_eq!(3, chest.files.len());
+        let names = |snapshot| {
+            chest
+                .snapshot_files(snapshot)
+                .unwrap()
+                .iter()
+                .map(|f| f.metadata.filename.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["a.conf", "b.conf"], names(Some(1)));
+        assert_eq!(vec!["b.conf", "c.conf"], names(None));
+        assert!(chest.snapshot_files(Some(3)).is_err());
+    }
+
+    #[test]
+    fn removed_solid_files_are_dropped_from_the_stream() {
+        let mut chest = UnlockedChest::new(
+            PASSWORD,
+            Some(CompressionAlgorithm::Zstd),
+            None,
+            Layout::Solid,
+        )
+        .unwrap();
+        ["first", "second", "third"].iter().for_each(|name| {
+            chest
+                .add_file_from_reader(name.as_bytes(), name.to_string(), None)
+                .unwrap();
+        });
+        let mut chest = chest.lock(PASSWORD).unwrap().unlock(PASSWORD).unwrap();
+        chest.remove_file("second").unwrap();
+        let chest = chest.lock(PASSWORD).unwrap().unlock(PASSWORD).unwrap();
+        let mut third = Vec::new();
+        chest
+            .decrypt_file_to_writer(chest.find_file("third").unwrap(), &mut third)
+            .unwrap();
+        assert_eq!(b"third".to_vec(), third);
+        assert!(chest.find_file("second").is_err());
+    }
+
+    #[test]
+    fn partial_reading_matches_the_serialized_fields() {
+        let blob = |len| padding::decoy(len).unwrap();
+        let chest = LockedChest {
+            format: FORMAT_VERSION,
+            public: Public::default(),
+            index: Some(blob(1)),
+            hidden_index: Some(blob(2)),
+            files: vec![
+                LockedFile {
+                    cipher: blob(3),
+                    metadata: blob(4),
+                };
+                2
+            ],
+            dictionary: Some(blob(5)),
+            solid: Some(LockedSolid {
+                index: blob(6),
+                stream: blob(7),
+            }),
+            chunks: BTreeMap::from([([1; 32], blob(8)), ([2; 32], blob(9))]),
+            snapshots: Some(blob(10)),
+            redundancy: None,
+            legacy: false,
+        };
+        let payload = bincode::serialize(&chest).unwrap();
+        let (partial, problem) = LockedChest::from_partial(&payload).unwrap();
+        assert!(problem.is_none());
+        assert_eq!(payload, bincode::serialize(&partial).unwrap());
+    }
+
+    #[test]
+    fn nested_relative_filenames_are_valid() {
+        assert!(validate_filename("dump.sql").is_ok());
+        assert!(validate_filename("backups/dump.sql").is_ok());
+        assert!(validate_filename("./dump.sql").is_ok());
+    }
+
+    #[test]
+    fn escaping_filenames_are_invalid() {
+        assert!(validate_filename("").is_err());
+        assert!(validate_filename("../dump.sql").is_err());
+        assert!(validate_filename("backups/../../dump.sql").is_err());
+        assert!(validate_filename("/etc/passwd").is_err());
+        assert!(validate_filename(".").is_err());
+        assert!(validate_filename("./.").is_err());
+        assert!(validate_filename("backups/").is_err());
     }
 }
@@ -1,13 +1,29 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::{Parser, Subcommand};
 
+use crate::adaptive::CompressionRule;
+use crate::chest::CompressionAlgorithm;
+use crate::compression::DEFAULT_DICTIONARY_SIZE;
+use crate::conflict::Overwrite;
+use crate::container::parse_redundancy;
+use crate::listing::SortKey;
+use crate::password::{HiddenPasswordArgs, PasswordArgs, StrengthArgs, TargetPasswordArgs};
+use crate::term::OutputFormat;
+
 #[derive(Debug, Parser)]
 #[command(name = "chest")]
 #[command(about = "Simple encrypted file storage cli tool")]
 pub(crate) struct Cli {
     #[command(subcommand)]
     pub(crate) command: Commands,
+    /// Output format
+    #[clap(long, value_enum, global = true, default_value_t)]
+    pub(crate) output: OutputFormat,
+    /// How long to wait for another process using the chest, e.g. 30s
+    #[clap(long, global = true, value_parser = humantime::parse_duration, default_value = "10s", value_name = "DURATION")]
+    pub(crate) lock_timeout: Duration,
 }
 
 #[derive(Debug, Subcommand)]
@@ -17,15 +33,66 @@ pub(crate) enum Commands {
     New {
         /// Chest name
         name: String,
-        /// Files to encrypt in the chest
+        /// Files to encrypt in the chest, use - to read from stdin
         #[arg(short, long, required = true, num_args(0..), value_name = "PATH")]
         add: Vec<PathBuf>,
+        /// Name of the file read from stdin
+        #[clap(long = "name", value_name = "NAME")]
+        stdin_name: Option<String>,
+        /// Compression algorithm, deflate or zstd with --dictionary if not
+        /// provided
+        #[clap(short, long, value_enum)]
+        compression: Option<CompressionAlgorithm>,
+        /// Compression level, use the algorithm default if not provided
+        #[clap(short, long, allow_negative_numbers = true)]
+        level: Option<i32>,
         /// Disable compression
-        #[clap(short, long, action)]
+        #[clap(short, long, action, conflicts_with_all = ["compression", "level"])]
         no_compression: bool,
-        /// Optional chest password, will be prompted if not provided
-        #[clap(short, long)]
-        password: Option<String>,
+        /// Compression for the files matching a name or MIME type pattern,
+        /// e.g. '*.log=zstd:19' or 'mime:image/*=none'
+        #[clap(long = "compress-rule", value_name = "PATTERN=ALGORITHM[:LEVEL]")]
+        compress_rules: Vec<CompressionRule>,
+        /// Train a zstd dictionary on the added files to better compress many
+        /// small similar files
+        #[clap(short, long, action, conflicts_with = "no_compression")]
+        dictionary: bool,
+        /// Compress all files as a single stream, for the best ratio at the
+        /// cost of decompressing the whole chest to extract any file
+        #[clap(long, action, conflicts_with_all = ["dictionary", "compress_rules"])]
+        solid: bool,
+        /// Split files in chunks and store identical chunks only once, for
+        /// chests of many versions of similar files
+        #[clap(
+            long,
+            action,
+            conflicts_with_all = ["dictionary", "compress_rules", "solid"]
+        )]
+        dedup: bool,
+        /// Add Reed-Solomon parity data to repair bit rot, as a percentage
+        /// of the chest size, e.g. 10%
+        #[clap(long, value_parser = parse_redundancy, value_name = "PERCENT")]
+        redundancy: Option<u8>,
+        /// Pad every encrypted blob to a size bucket and keep all metadata in
+        /// a single encrypted index, hiding file sizes and count
+        #[clap(long, action)]
+        pad: bool,
+        /// Add this many random decoy files, looking like real ones
+        #[clap(long, value_name = "N", requires = "pad", conflicts_with = "solid")]
+        decoys: Option<usize>,
+        /// Maximum size of the trained dictionary in bytes
+        #[clap(long, default_value_t = DEFAULT_DICTIONARY_SIZE, requires = "dictionary")]
+        dictionary_size: usize,
+        /// Overwrite the chest if it already exists
+        #[clap(short, long, action)]
+        force: bool,
+        /// Keep the overwritten chest as NAME.chest~
+        #[clap(long, action, requires = "force")]
+        backup: bool,
+        #[command(flatten)]
+        password: PasswordArgs,
+        #[command(flatten)]
+        strength: StrengthArgs,
     },
 
     /// Peek into a chest and list its content, decrypting only metadata
@@ -34,9 +101,17 @@ pub(crate) enum Commands {
         /// Chest file path
         #[clap(value_name = "PATH")]
         chest: PathBuf,
-        /// Optional chest password, will be prompted if not provided
-        #[clap(short, long)]
-        password: Option<String>,
+        /// Sort files by this column
+        #[clap(short, long, value_enum, default_value_t)]
+        sort: SortKey,
+        /// Reverse the sort order
+        #[clap(short, long, action)]
+        reverse: bool,
+        /// Display files as a directory tree
+        #[clap(short, long, action)]
+        tree: bool,
+        #[command(flatten)]
+        password: PasswordArgs,
     },
 
     /// Open a chest and extract its encrypted content
@@ -45,11 +120,162 @@ pub(crate) enum Commands {
         /// Chest file path
         #[clap(value_name = "PATH")]
         chest: PathBuf,
+        /// Only extract files matching these names or glob patterns
+        #[clap(value_name = "PATTERN")]
+        patterns: Vec<String>,
+        /// Skip files matching this name or glob pattern
+        #[clap(short, long, value_name = "PATTERN")]
+        exclude: Vec<String>,
         /// Output dir, use chest file name if not provided
         #[clap(short, long, value_name = "PATH")]
         out: Option<PathBuf>,
-        /// Optional chest password, will be prompted if not provided
-        #[clap(short, long)]
-        password: Option<String>,
+        /// Extract this snapshot instead of the latest one, as numbered by
+        /// chest log
+        #[clap(long, value_name = "N")]
+        snapshot: Option<usize>,
+        /// What to do with files already in the output dir
+        #[clap(long, value_enum, default_value_t, value_name = "WHEN")]
+        overwrite: Overwrite,
+        /// Extract files that aren't overwritten under a new name, e.g.
+        /// 'notes (1).txt', instead of skipping them
+        #[clap(long, action)]
+        rename_on_conflict: bool,
+        /// Extract every intact file of a damaged chest, reporting what was
+        /// lost instead of failing
+        #[clap(long)]
+        salvage: bool,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+
+    /// Decrypt a single file from a chest and write it to stdout
+    #[command(arg_required_else_help = true)]
+    Cat {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+        /// Name of the file to write to stdout
+        #[clap(value_name = "FILE")]
+        file: String,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+
+    /// Check that every file of a chest decrypts to its recorded size and
+    /// hash, without writing anything to disk
+    #[command(arg_required_else_help = true)]
+    Verify {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+
+    /// Check a chest file checksums for bit rot and truncation, without the
+    /// password
+    #[command(arg_required_else_help = true)]
+    Check {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+    },
+
+    /// Rebuild the damaged regions of a chest file from its parity data,
+    /// without the password
+    #[command(arg_required_else_help = true)]
+    Repair {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+        /// Keep the previous version of the chest as PATH~
+        #[clap(long, action)]
+        backup: bool,
+    },
+
+    /// Record the content of a directory as a new snapshot of a chest,
+    /// creating the chest if needed
+    #[command(arg_required_else_help = true)]
+    Snapshot {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+        /// Directory to snapshot
+        #[clap(value_name = "DIR")]
+        dir: PathBuf,
+        /// Keep the previous version of the chest as PATH~
+        #[clap(long, action)]
+        backup: bool,
+        #[command(flatten)]
+        password: PasswordArgs,
+        #[command(flatten)]
+        strength: StrengthArgs,
+    },
+
+    /// Update a chest from a directory, only storing new and changed files
+    #[command(arg_required_else_help = true)]
+    Sync {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+        /// Directory to sync the chest with
+        #[clap(value_name = "DIR")]
+        dir: PathBuf,
+        /// Delete the chest files missing from the directory
+        #[clap(long, action)]
+        delete: bool,
+        /// Keep the previous version of the chest as PATH~
+        #[clap(long, action)]
+        backup: bool,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+
+    /// Hide files in the decoys of a padded chest, opened by a second password
+    #[command(arg_required_else_help = true)]
+    Hide {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+        /// Files to hide, replacing the previously hidden ones
+        #[arg(short, long, required = true, num_args(1..), value_name = "PATH")]
+        add: Vec<PathBuf>,
+        /// Keep the previous version of the chest as PATH~
+        #[clap(long, action)]
+        backup: bool,
+        #[command(flatten)]
+        password: PasswordArgs,
+        #[command(flatten)]
+        strength: StrengthArgs,
+        #[command(flatten)]
+        hidden_password: HiddenPasswordArgs,
+    },
+
+    /// List the differences between a chest and a directory or another chest
+    #[command(arg_required_else_help = true)]
+    Diff {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+        /// Directory or chest file to compare the chest with
+        #[clap(value_name = "DIR|CHEST")]
+        target: PathBuf,
+        /// Show the content changes of modified text files
+        #[clap(short, long, action)]
+        content: bool,
+        #[command(flatten)]
+        password: PasswordArgs,
+        #[command(flatten)]
+        target_password: TargetPasswordArgs,
+    },
+
+    /// List the snapshots of a chest
+    #[command(arg_required_else_help = true)]
+    Log {
+        /// Chest file path
+        #[clap(value_name = "PATH")]
+        chest: PathBuf,
+        #[command(flatten)]
+        password: PasswordArgs,
     },
 }
@@ -1,38 +1,248 @@
-use std::io::{Read, Write};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
 
 use flate2::read::DeflateDecoder;
 use flate2::write::DeflateEncoder;
 use flate2::Compression;
 
-use crate::chest::CompressionAlgorithm;
-use crate::error::CompressResult;
+use crate::chest::{CompressionAlgorithm, FileCompression};
+use crate::error::{CompressError, CompressResult};
 
-pub(crate) fn get_compressor(algorithm: &CompressionAlgorithm) -> impl Compress {
+const DEFLATE_LEVELS: RangeInclusive<i32> = 0..=9;
+const DEFLATE_DEFAULT_LEVEL: i32 = 6;
+// lz4 frames have a single compression level
+const LZ4_LEVELS: RangeInclusive<i32> = 0..=0;
+const BROTLI_LEVELS: RangeInclusive<i32> = 0..=11;
+const BROTLI_DEFAULT_LEVEL: i32 = 9;
+#[cfg(feature = "brotli")]
+const BROTLI_BUFFER_SIZE: usize = 4096;
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW_SIZE: u32 = 22;
+const XZ_LEVELS: RangeInclusive<i32> = 0..=9;
+const XZ_DEFAULT_LEVEL: i32 = 6;
+// zstd's own default for trained dictionaries
+pub(crate) const DEFAULT_DICTIONARY_SIZE: usize = 112_640;
+
+pub(crate) fn get_compressor(
+    algorithm: &CompressionAlgorithm,
+    level: Option<i32>,
+) -> CompressResult<Box<dyn Compress>> {
+    let level = level.unwrap_or_else(|| default_level(algorithm));
+    let levels = levels(algorithm);
+    if !levels.contains(&level) {
+        return Err(CompressError::InvalidLevel {
+            algorithm: algorithm.clone(),
+            level,
+            levels,
+        });
+    }
+    Ok(match algorithm {
+        CompressionAlgorithm::Deflate => Box::new(DeflateCompressor {
+            level: level as u32,
+        }),
+        CompressionAlgorithm::Zstd => Box::new(ZstdCompressor {
+            level,
+            dictionary: None,
+        }),
+        #[cfg(feature = "lz4")]
+        CompressionAlgorithm::Lz4 => Box::new(Lz4Compressor),
+        #[cfg(feature = "brotli")]
+        CompressionAlgorithm::Brotli => Box::new(BrotliCompressor {
+            level: level as u32,
+        }),
+        #[cfg(feature = "xz")]
+        CompressionAlgorithm::Xz => Box::new(XzCompressor {
+            level: level as u32,
+        }),
+        #[allow(unreachable_patterns)]
+        _ => return Err(CompressError::Unsupported(algorithm.clone())),
+    })
+}
+
+pub(crate) fn get_file_compressor(
+    compression: &FileCompression,
+    dictionary: Option<&[u8]>,
+) -> CompressResult<Box<dyn Compress>> {
+    if !compression.dictionary {
+        return get_compressor(&compression.algorithm, Some(compression.level));
+    }
+    if compression.algorithm != CompressionAlgorithm::Zstd {
+        return Err(CompressError::DictionaryUnsupported(
+            compression.algorithm.clone(),
+        ));
+    }
+    let dictionary = dictionary.ok_or(CompressError::MissingDictionary)?;
+    get_compressor(&compression.algorithm, Some(compression.level))?;
+    Ok(Box::new(ZstdCompressor {
+        level: compression.level,
+        dictionary: Some(dictionary.to_vec()),
+    }))
+}
+
+pub(crate) fn train_dictionary<S: AsRef<[u8]>>(
+    samples: &[S],
+    max_size: usize,
+) -> CompressResult<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size).map_err(CompressError::Dictionary)
+}
+
+pub(crate) fn default_level(algorithm: &CompressionAlgorithm) -> i32 {
+    match algorithm {
+        CompressionAlgorithm::Deflate => DEFLATE_DEFAULT_LEVEL,
+        CompressionAlgorithm::Zstd => zstd::DEFAULT_COMPRESSION_LEVEL,
+        CompressionAlgorithm::Lz4 => *LZ4_LEVELS.start(),
+        CompressionAlgorithm::Brotli => BROTLI_DEFAULT_LEVEL,
+        CompressionAlgorithm::Xz => XZ_DEFAULT_LEVEL,
+    }
+}
+
+fn levels(algorithm: &CompressionAlgorithm) -> RangeInclusive<i32> {
     match algorithm {
-        CompressionAlgorithm::Deflate => DeflateCompressor,
+        CompressionAlgorithm::Deflate => DEFLATE_LEVELS,
+        CompressionAlgorithm::Zstd => zstd::compression_level_range(),
+        CompressionAlgorithm::Lz4 => LZ4_LEVELS,
+        CompressionAlgorithm::Brotli => BROTLI_LEVELS,
+        CompressionAlgorithm::Xz => XZ_LEVELS,
     }
 }
 
 pub(crate) trait Compress {
     fn compress(&self, payload: &[u8]) -> CompressResult<Vec<u8>>;
-    fn decompress(&self, payload: &[u8]) -> CompressResult<Vec<u8>>;
+    fn decompress_to(&self, payload: &[u8], writer: &mut dyn Write) -> CompressResult<u64>;
 }
 
-#[derive(Default)]
-pub(crate) struct DeflateCompressor;
+pub(crate) struct DeflateCompressor {
+    level: u32,
+}
+
+impl Default for DeflateCompressor {
+    fn default() -> Self {
+        Self {
+            level: DEFLATE_DEFAULT_LEVEL as u32,
+        }
+    }
+}
 
 impl Compress for DeflateCompressor {
     fn compress(&self, payload: &[u8]) -> CompressResult<Vec<u8>> {
-        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(self.level));
         encoder.write_all(payload)?;
         Ok(encoder.finish()?)
     }
 
-    fn decompress(&self, payload: &[u8]) -> CompressResult<Vec<u8>> {
+    fn decompress_to(&self, payload: &[u8], writer: &mut dyn Write) -> CompressResult<u64> {
         let mut decoder = DeflateDecoder::new(payload);
-        let mut buffer = Vec::new();
-        decoder.read_to_end(&mut buffer)?;
-        Ok(buffer)
+        Ok(io::copy(&mut decoder, writer)?)
+    }
+}
+
+pub(crate) struct ZstdCompressor {
+    level: i32,
+    dictionary: Option<Vec<u8>>,
+}
+
+impl Default for ZstdCompressor {
+    fn default() -> Self {
+        Self {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            dictionary: None,
+        }
+    }
+}
+
+impl Compress for ZstdCompressor {
+    fn compress(&self, payload: &[u8]) -> CompressResult<Vec<u8>> {
+        let dictionary = self.dictionary.as_deref().unwrap_or_default();
+        let mut encoder =
+            zstd::stream::write::Encoder::with_dictionary(Vec::new(), self.level, dictionary)?;
+        encoder.write_all(payload)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn decompress_to(&self, payload: &[u8], writer: &mut dyn Write) -> CompressResult<u64> {
+        let dictionary = self.dictionary.as_deref().unwrap_or_default();
+        let mut decoder = zstd::stream::read::Decoder::with_dictionary(payload, dictionary)?;
+        Ok(io::copy(&mut decoder, writer)?)
+    }
+}
+
+#[cfg(feature = "lz4")]
+#[derive(Default)]
+pub(crate) struct Lz4Compressor;
+
+#[cfg(feature = "lz4")]
+impl Compress for Lz4Compressor {
+    fn compress(&self, payload: &[u8]) -> CompressResult<Vec<u8>> {
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(payload)?;
+        Ok(encoder.finish().map_err(io::Error::other)?)
+    }
+
+    fn decompress_to(&self, payload: &[u8], writer: &mut dyn Write) -> CompressResult<u64> {
+        let mut decoder = lz4_flex::frame::FrameDecoder::new(payload);
+        Ok(io::copy(&mut decoder, writer)?)
+    }
+}
+
+#[cfg(feature = "brotli")]
+pub(crate) struct BrotliCompressor {
+    level: u32,
+}
+
+#[cfg(feature = "brotli")]
+impl Default for BrotliCompressor {
+    fn default() -> Self {
+        Self {
+            level: BROTLI_DEFAULT_LEVEL as u32,
+        }
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl Compress for BrotliCompressor {
+    fn compress(&self, payload: &[u8]) -> CompressResult<Vec<u8>> {
+        let mut encoder = brotli::CompressorWriter::new(
+            Vec::new(),
+            BROTLI_BUFFER_SIZE,
+            self.level,
+            BROTLI_WINDOW_SIZE,
+        );
+        encoder.write_all(payload)?;
+        encoder.flush()?;
+        Ok(encoder.into_inner())
+    }
+
+    fn decompress_to(&self, payload: &[u8], writer: &mut dyn Write) -> CompressResult<u64> {
+        let mut decoder = brotli::Decompressor::new(payload, BROTLI_BUFFER_SIZE);
+        Ok(io::copy(&mut decoder, writer)?)
+    }
+}
+
+#[cfg(feature = "xz")]
+pub(crate) struct XzCompressor {
+    level: u32,
+}
+
+#[cfg(feature = "xz")]
+impl Default for XzCompressor {
+    fn default() -> Self {
+        Self {
+            level: XZ_DEFAULT_LEVEL as u32,
+        }
+    }
+}
+
+#[cfg(feature = "xz")]
+impl Compress for XzCompressor {
+    fn compress(&self, payload: &[u8]) -> CompressResult<Vec<u8>> {
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), self.level);
+        encoder.write_all(payload)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn decompress_to(&self, payload: &[u8], writer: &mut dyn Write) -> CompressResult<u64> {
+        let mut decoder = xz2::read::XzDecoder::new(payload);
+        Ok(io::copy(&mut decoder, writer)?)
     }
 }
 
@@ -41,24 +251,121 @@ mod tests {
     use super::*;
 
     const PAYLOAD: &[u8; 9] = b"aaaaaaaaa";
-    const COMPRESSOR: DeflateCompressor = DeflateCompressor;
+    const COMPRESSOR: DeflateCompressor = DeflateCompressor {
+        level: DEFLATE_DEFAULT_LEVEL as u32,
+    };
 
     #[test]
     fn compressed_then_decompressed_data_is_similar() {
-        let compressed = COMPRESSOR.compress(&PAYLOAD.to_vec()).unwrap();
-        let decompressed = COMPRESSOR.decompress(&compressed).unwrap();
+        let compressed = COMPRESSOR.compress(PAYLOAD).unwrap();
+        let mut decompressed = Vec::new();
+        let written = COMPRESSOR
+            .decompress_to(&compressed, &mut decompressed)
+            .unwrap();
+        assert_eq!(PAYLOAD.len() as u64, written);
         assert_eq!(PAYLOAD.to_vec(), decompressed);
     }
 
     #[test]
     fn compressed_data_is_different() {
-        let compressed = COMPRESSOR.compress(&PAYLOAD.to_vec()).unwrap();
+        let compressed = COMPRESSOR.compress(PAYLOAD).unwrap();
         assert_ne!(PAYLOAD.to_vec(), compressed);
     }
 
     #[test]
     fn compressed_data_is_smaller() {
-        let compressed = COMPRESSOR.compress(&PAYLOAD.to_vec()).unwrap();
+        let compressed = COMPRESSOR.compress(PAYLOAD).unwrap();
         assert!(PAYLOAD.to_vec().len() > compressed.len());
     }
+
+    #[test]
+    fn zstd_compressed_then_decompressed_data_is_similar() {
+        assert_roundtrip(&ZstdCompressor::default());
+    }
+
+    fn assert_roundtrip(compressor: &dyn Compress) {
+        let payload = PAYLOAD.repeat(100);
+        let compressed = compressor.compress(&payload).unwrap();
+        let mut decompressed = Vec::new();
+        compressor
+            .decompress_to(&compressed, &mut decompressed)
+            .unwrap();
+        assert!(payload.len() > compressed.len());
+        assert_eq!(payload, decompressed);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_compressed_then_decompressed_data_is_similar() {
+        assert_roundtrip(&Lz4Compressor);
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_compressed_then_decompressed_data_is_similar() {
+        assert_roundtrip(&BrotliCompressor::default());
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz_compressed_then_decompressed_data_is_similar() {
+        assert_roundtrip(&XzCompressor::default());
+    }
+
+    #[cfg(not(feature = "xz"))]
+    #[test]
+    fn disabled_algorithm_is_unsupported() {
+        assert!(matches!(
+            get_compressor(&CompressionAlgorithm::Xz, None),
+            Err(CompressError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn zstd_with_dictionary_compressed_then_decompressed_data_is_similar() {
+        let samples = (0..1000)
+            .map(|i| format!("{{\"id\": {i}, \"name\": \"user-{i}\", \"active\": true}}"))
+            .collect::<Vec<_>>();
+        let dictionary = train_dictionary(&samples, 1024).unwrap();
+        let compressor = ZstdCompressor {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            dictionary: Some(dictionary),
+        };
+        let payload = br#"{"id": 1234, "name": "user-1234", "active": true}"#;
+        let compressed = compressor.compress(payload).unwrap();
+        let mut decompressed = Vec::new();
+        compressor
+            .decompress_to(&compressed, &mut decompressed)
+            .unwrap();
+        assert!(compressed.len() < ZstdCompressor::default().compress(payload).unwrap().len());
+        assert_eq!(payload.to_vec(), decompressed);
+    }
+
+    #[test]
+    fn few_small_samples_cannot_train_a_dictionary() {
+        let samples = [br#"{"id": 1}"#.to_vec(), br#"{"id": 2}"#.to_vec()];
+        assert!(matches!(
+            train_dictionary(&samples, 1024),
+            Err(CompressError::Dictionary(_))
+        ));
+    }
+
+    #[test]
+    fn dictionary_is_required_to_decompress() {
+        let compression = FileCompression {
+            algorithm: CompressionAlgorithm::Zstd,
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            dictionary: true,
+        };
+        assert!(matches!(
+            get_file_compressor(&compression, None),
+            Err(CompressError::MissingDictionary)
+        ));
+    }
+
+    #[test]
+    fn out_of_range_level_is_rejected() {
+        assert!(get_compressor(&CompressionAlgorithm::Deflate, Some(10)).is_err());
+        assert!(get_compressor(&CompressionAlgorithm::Zstd, Some(19)).is_ok());
+    }
 }
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use clap::ValueEnum;
+
+use crate::chest::{validate_filename, UnlockedFile};
+use crate::error::ChestResult;
+
+/// What to do with files already in the output folder
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Overwrite {
+    /// Keep existing files
+    #[default]
+    Never,
+    Always,
+    /// Only replace files modified before the chest file
+    Newer,
+    /// Ask for each file
+    Prompt,
+}
+
+/// Existing file an extracted file would replace
+pub(crate) struct Conflict {
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+    /// Whether the chest file was modified after the existing one
+    pub(crate) newer: bool,
+}
+
+/// Where each file is extracted, decided before anything is written
+pub(crate) struct Plan<'a> {
+    pub(crate) writes: Vec<(&'a UnlockedFile, PathBuf)>,
+    /// Conflicting files written under a new name, with that name
+    pub(crate) renamed: Vec<(String, String)>,
+    pub(crate) skipped: Vec<String>,
+}
+
+/// Files that already exist in the output folder, validating every name
+/// first so nothing is written outside of it
+pub(crate) fn find(files: &[&UnlockedFile], out: &Path) -> ChestResult<Vec<Conflict>> {
+    files
+        .iter()
+        .filter_map(|f| {
+            if let Err(e) = validate_filename(&f.metadata.filename) {
+                return Some(Err(e));
+            }
+            let path = out.join(&f.metadata.filename);
+            let existing = fs::symlink_metadata(&path)
+                .ok()?
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            Some(Ok(Conflict {
+                name: f.metadata.filename.clone(),
+                path,
+                newer: matches!((f.metadata.modified, existing), (Some(c), Some(e)) if c > e),
+            }))
+        })
+        .collect()
+}
+
+/// Resolve the conflicts, files that aren't overwritten are skipped or
+/// written under a free name if `rename` is set
+pub(crate) fn plan<'a>(
+    files: &[&'a UnlockedFile],
+    out: &Path,
+    conflicts: &[Conflict],
+    overwrite: Overwrite,
+    rename: bool,
+    mut confirm: impl FnMut(&Conflict) -> bool,
+) -> Plan<'a> {
+    let mut plan = Plan {
+        writes: Vec::new(),
+        renamed: Vec::new(),
+        skipped: Vec::new(),
+    };
+    // renamed files must not take the path of another extracted file
+    let mut taken = files
+        .iter()
+        .map(|f| out.join(&f.metadata.filename))
+        .collect::<HashSet<_>>();
+    for f in files {
+        let path = out.join(&f.metadata.filename);
+        let Some(conflict) = conflicts.iter().find(|c| c.path == path) else {
+            plan.writes.push((f, path));
+            continue;
+        };
+        let replace = match overwrite {
+            Overwrite::Never => false,
+            Overwrite::Always => true,
+            Overwrite::Newer => conflict.newer,
+            Overwrite::Prompt => confirm(conflict),
+        };
+        if replace {
+            plan.writes.push((f, path));
+        } else if rename {
+            let free = free_path(&path, &taken);
+            taken.insert(free.clone());
+            let new_name = free.strip_prefix(out).unwrap_or(&free);
+            plan.renamed
+                .push((conflict.name.clone(), new_name.display().to_string()));
+            plan.writes.push((f, free));
+        } else {
+            plan.skipped.push(conflict.name.clone());
+        }
+    }
+    plan
+}
+
+/// Create the file to extract and its folders, replacing an existing symlink
+/// rather than writing through it outside of the output folder
+pub(crate) fn create(path: &Path) -> io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+        fs::remove_file(path)?;
+    }
+    fs::File::create(path)
+}
+
+/// First of `name (1).ext`, `name (2).ext`... that doesn't exist
+fn free_path(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    (1..)
+        .map(|i| {
+            let mut name = OsString::from(stem);
+            name.push(format!(" ({i})"));
+            if let Some(extension) = path.extension() {
+                name.push(".");
+                name.push(extension);
+            }
+            path.with_file_name(name)
+        })
+        .find(|p| !taken.contains(p) && fs::symlink_metadata(p).is_err())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chest::{Layout, UnlockedChest};
+
+    #[test]
+    fn conflicts_are_resolved_by_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path();
+        fs::write(out.join("a.txt"), b"existing").unwrap();
+        fs::write(out.join("a (1).txt"), b"existing").unwrap();
+        let mut chest = UnlockedChest::new("password", None, None, Layout::Blobs).unwrap();
+        ["a.txt", "b.txt"].iter().for_each(|name| {
+            chest
+                .add_file_from_reader(&b"data"[..], name.to_string(), None)
+                .unwrap();
+        });
+        let files = chest.files.iter().collect::<Vec<_>>();
+        let conflicts = find(&files, out).unwrap();
+        assert_eq!(1, conflicts.len());
+        assert!(!conflicts[0].newer);
+
+        let never = plan(&files, out, &conflicts, Overwrite::Never, false, |_| true);
+        assert_eq!(vec!["a.txt".to_string()], never.skipped);
+        assert_eq!(1, never.writes.len());
+        let newer = plan(&files, out, &conflicts, Overwrite::Newer, false, |_| true);
+        assert_eq!(1, newer.skipped.len());
+        let prompt = plan(&files, out, &conflicts, Overwrite::Prompt, false, |_| true);
+        assert_eq!(2, prompt.writes.len());
+        let renamed = plan(&files, out, &conflicts, Overwrite::Never, true, |_| true);
+        assert_eq!("a (2).txt", renamed.renamed[0].1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_replaced_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+        let outside = dir.path().join("outside");
+        fs::write(&outside, b"outside").unwrap();
+        std::os::unix::fs::symlink(&outside, out.join("a.txt")).unwrap();
+        let mut file = create(&out.join("a.txt")).unwrap();
+        io::Write::write_all(&mut file, b"extracted").unwrap();
+        assert_eq!(b"outside", &fs::read(&outside).unwrap()[..]);
+        assert_eq!(b"extracted", &fs::read(out.join("a.txt")).unwrap()[..]);
+        assert!(!fs::symlink_metadata(out.join("a.txt"))
+            .unwrap()
+            .is_symlink());
+    }
+}
@@ -0,0 +1,534 @@
+use std::fmt;
+use std::ops::Range;
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+
+use crate::chest::sha256;
+use crate::error::{ChestError, ChestResult};
+
+const MAGIC: &[u8] = b"CHEST";
+const VERSION: u8 = 1;
+const MIN_BLOCK_BYTES: usize = 512;
+const MAX_BLOCK_BYTES: usize = 64 * 1024;
+// data blocks sharing parity blocks, with at most as many parity blocks this
+// stays below the 256 shards Reed-Solomon over GF(2^8) allows
+const STRIPE_DATA_BLOCKS: usize = 128;
+// magic, version, header length and header hash
+const PREFIX_BYTES: usize = MAGIC.len() + 1 + 4 + 32;
+// header hash, header length, version and magic after the footer header copy
+const FOOTER_SUFFIX_BYTES: usize = 32 + 4 + 1 + MAGIC.len();
+
+/// Unkeyed checksums of the serialized chest, checked without the password
+#[derive(Clone, Serialize, Deserialize)]
+struct Header {
+    body_bytes: u64,
+    block_bytes: u32,
+    block_sha256: Vec<[u8; 32]>,
+    parity: Option<Parity>,
+}
+
+/// Reed-Solomon parity blocks following the body, a copy of the header being
+/// written after them
+#[derive(Clone, Serialize, Deserialize)]
+struct Parity {
+    percent: u8,
+    block_sha256: Vec<[u8; 32]>,
+}
+
+/// Damaged region of a chest file, with its absolute byte range
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Damage {
+    Header { start: u64, end: u64 },
+    Block { index: usize, start: u64, end: u64 },
+    ParityBlock { index: usize, start: u64, end: u64 },
+    Truncated { expected: u64, actual: u64 },
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Damage::Header { start, end } => {
+                write!(f, "header (bytes {start}-{end}) doesn't match its checksum")
+            }
+            Damage::Block { index, start, end } => {
+                write!(
+                    f,
+                    "block {index} (bytes {start}-{end}) doesn't match its checksum"
+                )
+            }
+            Damage::ParityBlock { index, start, end } => {
+                write!(
+                    f,
+                    "parity block {index} (bytes {start}-{end}) doesn't match its checksum"
+                )
+            }
+            Damage::Truncated { expected, actual } => {
+                write!(
+                    f,
+                    "file is truncated to {actual} bytes, expected {expected}"
+                )
+            }
+        }
+    }
+}
+
+/// Result of checking a chest file
+pub(crate) struct Checked<'a> {
+    /// Serialized chest, possibly damaged or truncated
+    pub(crate) body: &'a [u8],
+    /// Number of checksummed blocks, `None` for chests written before
+    /// checksums were added
+    pub(crate) blocks: Option<usize>,
+    /// Percentage of parity blocks, if any
+    pub(crate) redundancy: Option<u8>,
+    pub(crate) damage: Vec<Damage>,
+}
+
+/// Header and where the regions it describes are in the file
+struct Parsed {
+    header: Header,
+    header_bytes: usize,
+    header_damage: Option<Damage>,
+}
+
+impl Parsed {
+    fn body_start(&self) -> usize {
+        PREFIX_BYTES + self.header_bytes
+    }
+
+    fn parity_start(&self) -> usize {
+        self.body_start() + self.header.body_bytes as usize
+    }
+
+    fn block_range(&self, index: usize) -> Range<usize> {
+        let block_bytes = self.header.block_bytes as usize;
+        let start = self.body_start() + index * block_bytes;
+        start..(start + block_bytes).min(self.parity_start())
+    }
+
+    fn parity_range(&self, index: usize) -> Range<usize> {
+        let start = self.parity_start() + index * self.header.block_bytes as usize;
+        start..start + self.header.block_bytes as usize
+    }
+
+    fn expected_bytes(&self) -> usize {
+        match &self.header.parity {
+            Some(parity) => {
+                self.parity_range(parity.block_sha256.len()).start
+                    + self.header_bytes
+                    + FOOTER_SUFFIX_BYTES
+            }
+            None => self.parity_start(),
+        }
+    }
+}
+
+pub(crate) fn parse_redundancy(value: &str) -> Result<u8, String> {
+    let percent = value
+        .strip_suffix('%')
+        .unwrap_or(value)
+        .parse::<u8>()
+        .map_err(|e| e.to_string())?;
+    match percent {
+        1..=100 => Ok(percent),
+        _ => Err(format!("expected 1% to 100%, got {value}")),
+    }
+}
+
+/// Frame the serialized chest with a header of its block checksums, and
+/// parity blocks for the given percentage of redundancy
+pub(crate) fn wrap(body: &[u8], redundancy: Option<u8>) -> ChestResult<Vec<u8>> {
+    let block_bytes = body
+        .len()
+        .div_ceil(STRIPE_DATA_BLOCKS)
+        .next_power_of_two()
+        .clamp(MIN_BLOCK_BYTES, MAX_BLOCK_BYTES);
+    let parity_blocks = redundancy
+        .map(|percent| parity_blocks(body, block_bytes, percent))
+        .unwrap_or_default();
+    let header = bincode::serialize(&Header {
+        body_bytes: body.len() as u64,
+        block_bytes: block_bytes as u32,
+        block_sha256: body.chunks(block_bytes).map(sha256).collect(),
+        parity: redundancy.map(|percent| Parity {
+            percent,
+            block_sha256: parity_blocks.iter().map(|b| sha256(b)).collect(),
+        }),
+    })?;
+    let header_sha256 = sha256(&header);
+    let mut file = Vec::new();
+    file.extend_from_slice(MAGIC);
+    file.push(VERSION);
+    file.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    file.extend_from_slice(&header_sha256);
+    file.extend_from_slice(&header);
+    file.extend_from_slice(body);
+    if redundancy.is_some() {
+        parity_blocks.iter().for_each(|b| file.extend_from_slice(b));
+        file.extend_from_slice(&header);
+        file.extend_from_slice(&header_sha256);
+        file.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        file.push(VERSION);
+        file.extend_from_slice(MAGIC);
+    }
+    Ok(file)
+}
+
+/// Check the chest file checksums, locating any damage
+pub(crate) fn check(file: &[u8]) -> ChestResult<Checked<'_>> {
+    // a damaged magic still leaves the header checksums to recognize the file
+    let legacy =
+        !file.starts_with(MAGIC) && prefix_header(file).is_none() && footer_header(file).is_none();
+    if legacy {
+        return Ok(Checked {
+            body: file,
+            blocks: None,
+            redundancy: None,
+            damage: Vec::new(),
+        });
+    }
+    let parsed = match parse(file)? {
+        Ok(parsed) => parsed,
+        Err(damage) => {
+            // the header length may still tell where the body starts
+            let body_start = header_len(file).map(|len| PREFIX_BYTES + len);
+            return Ok(Checked {
+                body: body_start
+                    .and_then(|start| file.get(start..))
+                    .unwrap_or_default(),
+                blocks: None,
+                redundancy: None,
+                damage: vec![damage],
+            });
+        }
+    };
+    let mut damage = parsed.header_damage.iter().cloned().collect::<Vec<_>>();
+    damage.extend(
+        damaged_blocks(file, &parsed)
+            .into_iter()
+            .map(|index| Damage::Block {
+                index,
+                start: clamped(parsed.block_range(index).start, file),
+                end: clamped(parsed.block_range(index).end, file),
+            }),
+    );
+    damage.extend(
+        damaged_parity_blocks(file, &parsed)
+            .into_iter()
+            .map(|index| Damage::ParityBlock {
+                index,
+                start: clamped(parsed.parity_range(index).start, file),
+                end: clamped(parsed.parity_range(index).end, file),
+            }),
+    );
+    let footer_start = parsed.expected_bytes() - parsed.header_bytes - FOOTER_SUFFIX_BYTES;
+    let footer_damaged = parsed.header.parity.is_some()
+        && parsed.header_damage.is_none()
+        && file.len() >= parsed.expected_bytes()
+        && footer_header(file).is_none();
+    if footer_damaged {
+        damage.push(Damage::Header {
+            start: footer_start as u64,
+            end: file.len() as u64,
+        });
+    }
+    if file.len() < parsed.expected_bytes() {
+        damage.push(Damage::Truncated {
+            expected: parsed.expected_bytes() as u64,
+            actual: file.len() as u64,
+        });
+    }
+    let body_end = parsed.parity_start().min(file.len());
+    Ok(Checked {
+        body: file.get(parsed.body_start()..body_end).unwrap_or_default(),
+        blocks: Some(parsed.header.block_sha256.len()),
+        redundancy: parsed.header.parity.as_ref().map(|p| p.percent),
+        damage,
+    })
+}
+
+/// Rebuild the damaged regions of the chest file from its parity blocks and
+/// header copy, returning the repaired file
+pub(crate) fn repair(file: &[u8]) -> ChestResult<Vec<u8>> {
+    let damage = check(file)?.damage;
+    let Ok(parsed) = parse(file)? else {
+        return Err(ChestError::Unrepairable(damage));
+    };
+    let Some(parity) = &parsed.header.parity else {
+        return Err(ChestError::Unrepairable(damage));
+    };
+    let block_bytes = parsed.header.block_bytes as usize;
+    let damaged = damaged_blocks(file, &parsed);
+    let damaged_parity = damaged_parity_blocks(file, &parsed);
+    let mut body = Vec::with_capacity(parsed.header.body_bytes as usize);
+    let mut parity_offset = 0;
+    for (data, parity_count) in stripes(parsed.header.block_sha256.len(), parity.percent) {
+        let data_shards = data.clone().map(|index| {
+            (!damaged.contains(&index))
+                .then(|| padded(&file[parsed.block_range(index)], block_bytes))
+        });
+        let parity_shards = (parity_offset..parity_offset + parity_count).map(|index| {
+            (!damaged_parity.contains(&index)).then(|| file[parsed.parity_range(index)].to_vec())
+        });
+        let mut shards = data_shards.chain(parity_shards).collect::<Vec<_>>();
+        if shards.iter().any(Option::is_none) {
+            let codec = ReedSolomon::new(data.len(), parity_count).expect("Valid stripe");
+            if codec.reconstruct_data(&mut shards).is_err() {
+                return Err(ChestError::Unrepairable(damage));
+            }
+        }
+        shards
+            .into_iter()
+            .take(data.len())
+            .flatten()
+            .for_each(|shard| body.extend_from_slice(&shard));
+        parity_offset += parity_count;
+    }
+    body.truncate(parsed.header.body_bytes as usize);
+    let repaired = wrap(&body, Some(parity.percent))?;
+    match check(&repaired)?.damage.is_empty() {
+        true => Ok(repaired),
+        false => Err(ChestError::Unrepairable(damage)),
+    }
+}
+
+/// Header from the start of the file, or from its copy at the end of the
+/// file when its magic, version or checksum is damaged
+fn parse(file: &[u8]) -> ChestResult<Result<Parsed, Damage>> {
+    let version = file.get(MAGIC.len()).copied().unwrap_or_default();
+    let prefix = prefix_header(file);
+    if let Some(header) = prefix.filter(|_| file.starts_with(MAGIC) && version == VERSION) {
+        return Ok(Ok(Parsed {
+            header: bincode::deserialize(header)?,
+            header_bytes: header.len(),
+            header_damage: None,
+        }));
+    }
+    let header_damage = |len| Damage::Header {
+        start: 0,
+        end: clamped(PREFIX_BYTES + len, file),
+    };
+    match (footer_header(file), prefix) {
+        (Some((header, VERSION)), _) => Ok(Ok(Parsed {
+            header: bincode::deserialize(header)?,
+            header_bytes: header.len(),
+            header_damage: Some(header_damage(header.len())),
+        })),
+        // both copies were written by a newer chest
+        (Some((_, footer_version)), _) if footer_version == version => {
+            Err(ChestError::UnsupportedVersion(version.into()))
+        }
+        (Some(_), _) => Ok(Err(header_damage(header_len(file).unwrap_or_default()))),
+        // without parity the header has no copy to compare the version with
+        (None, Some(_)) if file.starts_with(MAGIC) && version != VERSION => {
+            Err(ChestError::UnsupportedVersion(version.into()))
+        }
+        (None, Some(header)) => Ok(Ok(Parsed {
+            header: bincode::deserialize(header)?,
+            header_bytes: header.len(),
+            header_damage: Some(header_damage(header.len())),
+        })),
+        (None, None) => Ok(Err(header_damage(header_len(file).unwrap_or_default()))),
+    }
+}
+
+fn header_len(file: &[u8]) -> Option<usize> {
+    file.get(MAGIC.len() + 1..PREFIX_BYTES - 32)
+        .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+}
+
+/// Header at the start of the file matching its checksum, whatever the magic
+/// and version before it
+fn prefix_header(file: &[u8]) -> Option<&[u8]> {
+    let header = file.get(PREFIX_BYTES..PREFIX_BYTES.checked_add(header_len(file)?)?)?;
+    (sha256(header) == file[PREFIX_BYTES - 32..PREFIX_BYTES]).then_some(header)
+}
+
+/// Header copy at the end of the file matching its checksum, with the version
+/// written next to it
+fn footer_header(file: &[u8]) -> Option<(&[u8], u8)> {
+    let suffix = file.get(file.len().checked_sub(FOOTER_SUFFIX_BYTES)?..)?;
+    if !suffix.ends_with(MAGIC) {
+        return None;
+    }
+    let header_bytes = u32::from_le_bytes(suffix[32..36].try_into().unwrap()) as usize;
+    let start = file.len().checked_sub(FOOTER_SUFFIX_BYTES + header_bytes)?;
+    let header = &file[start..file.len() - FOOTER_SUFFIX_BYTES];
+    (sha256(header) == suffix[..32]).then_some((header, suffix[36]))
+}
+
+fn damaged_blocks(file: &[u8], parsed: &Parsed) -> Vec<usize> {
+    damaged(file, &parsed.header.block_sha256, |i| parsed.block_range(i))
+}
+
+fn damaged_parity_blocks(file: &[u8], parsed: &Parsed) -> Vec<usize> {
+    parsed
+        .header
+        .parity
+        .as_ref()
+        .map_or_else(Vec::new, |parity| {
+            damaged(file, &parity.block_sha256, |i| parsed.parity_range(i))
+        })
+}
+
+/// Indices of the blocks missing from the file or not matching their hash
+fn damaged(file: &[u8], hashes: &[[u8; 32]], range: impl Fn(usize) -> Range<usize>) -> Vec<usize> {
+    hashes
+        .iter()
+        .enumerate()
+        .filter(|(index, sha)| {
+            file.get(range(*index))
+                .is_none_or(|block| sha256(block) != **sha)
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn clamped(offset: usize, file: &[u8]) -> u64 {
+    offset.min(file.len()) as u64
+}
+
+/// Data block ranges sharing parity blocks, with their parity block count
+fn stripes(blocks: usize, percent: u8) -> Vec<(Range<usize>, usize)> {
+    (0..blocks)
+        .step_by(STRIPE_DATA_BLOCKS)
+        .map(|start| {
+            let data = start..(start + STRIPE_DATA_BLOCKS).min(blocks);
+            let parity = (data.len() * percent as usize).div_ceil(100).max(1);
+            (data, parity)
+        })
+        .collect()
+}
+
+fn parity_blocks(body: &[u8], block_bytes: usize, percent: u8) -> Vec<Vec<u8>> {
+    let blocks = body.chunks(block_bytes).collect::<Vec<_>>();
+    stripes(blocks.len(), percent)
+        .into_iter()
+        .flat_map(|(data, parity_count)| {
+            let mut shards = blocks[data.clone()]
+                .iter()
+                .map(|b| padded(b, block_bytes))
+                .chain((0..parity_count).map(|_| vec![0; block_bytes]))
+                .collect::<Vec<_>>();
+            ReedSolomon::new(data.len(), parity_count)
+                .expect("Valid stripe")
+                .encode(&mut shards)
+                .expect("Shards have the same length");
+            shards.split_off(data.len())
+        })
+        .collect()
+}
+
+fn padded(block: &[u8], block_bytes: usize) -> Vec<u8> {
+    let mut block = block.to_vec();
+    block.resize(block_bytes, 0);
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 96 blocks of 2 KiB
+    fn body() -> Vec<u8> {
+        (0..MAX_BLOCK_BYTES * 3).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn intact_file_has_no_damage() {
+        let body = body();
+        let file = wrap(&body, None).unwrap();
+        let checked = check(&file).unwrap();
+        assert_eq!(body, checked.body);
+        assert_eq!(Some(96), checked.blocks);
+        assert!(checked.damage.is_empty());
+    }
+
+    #[test]
+    fn flipped_bit_is_located() {
+        let mut file = wrap(&body(), None).unwrap();
+        let offset = file.len() - 10;
+        file[offset] ^= 1;
+        let damage = check(&file).unwrap().damage;
+        assert_eq!(1, damage.len());
+        assert!(matches!(damage[0], Damage::Block { index: 95, start, end }
+            if start as usize <= offset && offset < end as usize));
+    }
+
+    #[test]
+    fn truncation_is_detected() {
+        let file = wrap(&body(), None).unwrap();
+        let damage = check(&file[..file.len() - 100]).unwrap().damage;
+        assert!(matches!(damage[0], Damage::Block { index: 95, .. }));
+        assert!(matches!(damage[1], Damage::Truncated { .. }));
+    }
+
+    #[test]
+    fn legacy_files_are_read_as_is() {
+        let checked = check(b"legacy chest").unwrap();
+        assert_eq!(b"legacy chest", checked.body);
+        assert_eq!(None, checked.blocks);
+    }
+
+    #[test]
+    fn redundancy_is_parsed_as_percentage() {
+        assert_eq!(Ok(10), parse_redundancy("10%"));
+        assert_eq!(Ok(25), parse_redundancy("25"));
+        assert!(parse_redundancy("0%").is_err());
+        assert!(parse_redundancy("150%").is_err());
+    }
+
+    #[test]
+    fn damaged_regions_are_repaired() {
+        let file = wrap(&body(), Some(10)).unwrap();
+        let mut damaged = file.clone();
+        // header, two blocks and a parity block
+        [10, 5_000, 100_000, file.len() - 20_000]
+            .iter()
+            .for_each(|offset| damaged[*offset] ^= 0xff);
+        assert_eq!(4, check(&damaged).unwrap().damage.len());
+        assert_eq!(file, repair(&damaged).unwrap());
+    }
+
+    #[test]
+    fn damaged_header_copy_is_reported() {
+        let mut file = wrap(&body(), Some(10)).unwrap();
+        let offset = file.len() - FOOTER_SUFFIX_BYTES - 5;
+        file[offset] ^= 1;
+        let damage = check(&file).unwrap().damage;
+        assert!(matches!(damage[..], [Damage::Header { start, .. }] if start as usize <= offset));
+    }
+
+    #[test]
+    fn damaged_magic_and_version_are_repaired() {
+        let file = wrap(&body(), Some(10)).unwrap();
+        [0, MAGIC.len()].iter().for_each(|offset| {
+            let mut damaged = file.clone();
+            damaged[*offset] ^= 0x40;
+            let damage = check(&damaged).unwrap().damage;
+            assert!(matches!(damage[..], [Damage::Header { start: 0, .. }]));
+            assert_eq!(file, repair(&damaged).unwrap());
+        });
+    }
+
+    #[test]
+    fn newer_version_needs_both_copies_to_agree() {
+        let mut file = wrap(&body(), Some(10)).unwrap();
+        file[MAGIC.len()] = VERSION + 1;
+        let footer_version = file.len() - MAGIC.len() - 1;
+        file[footer_version] = VERSION + 1;
+        assert!(matches!(
+            check(&file),
+            Err(ChestError::UnsupportedVersion(v)) if v == u16::from(VERSION + 1)
+        ));
+    }
+
+    #[test]
+    fn too_much_damage_is_unrepairable() {
+        let mut file = wrap(&body(), Some(1)).unwrap();
+        file[5_000] ^= 1;
+        file[100_000] ^= 1;
+        assert!(matches!(repair(&file), Err(ChestError::Unrepairable(_))));
+    }
+}
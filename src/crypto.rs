@@ -1,5 +1,4 @@
 use ring::aead;
-use serde::{Deserialize, Serialize};
 
 use crate::{
     chest::{EncryptedBlob, EncryptionAlgorithm},
@@ -7,8 +6,9 @@ use crate::{
     random::generate_random_bytes,
 };
 
-const SALT_LENGTH: usize = 8;
-const NONCE_LENGTH: usize = 12;
+pub(crate) const SALT_LENGTH: usize = 8;
+pub(crate) const NONCE_LENGTH: usize = 12;
+pub(crate) const TAG_LENGTH: usize = 16;
 
 pub(crate) fn get_encryptor(algorithm: &EncryptionAlgorithm) -> impl Encrypt {
     match algorithm {
@@ -16,13 +16,6 @@ pub(crate) fn get_encryptor(algorithm: &EncryptionAlgorithm) -> impl Encrypt {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub(crate) struct EncryptedPayload {
-    pub(crate) cipher: Vec<u8>,
-    pub(crate) salt: Vec<u8>,
-    pub(crate) nonce: Vec<u8>,
-}
-
 pub(crate) trait Encrypt {
     fn encrypt(&self, payload: Vec<u8>, key: &[u8; 32]) -> EncryptResult<EncryptedBlob>;
     fn decrypt(&self, payload: &EncryptedBlob, key: &[u8; 32]) -> EncryptResult<Vec<u8>>;
@@ -0,0 +1,296 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::chest::{sha256, UnlockedChest};
+use crate::error::ChestResult;
+use crate::walk::walk_dir;
+
+const CONTEXT_LINES: usize = 3;
+// above this many compared line pairs, text diffs would take too much memory
+const MAX_DIFF_CELLS: usize = 25_000_000;
+
+/// Chest or directory being compared
+pub(crate) enum Side {
+    Chest(Box<UnlockedChest>),
+    Dir(PathBuf),
+}
+
+pub(crate) struct FileState {
+    pub(crate) name: String,
+    pub(crate) size_bytes: u64,
+    pub(crate) modified: Option<u64>,
+    pub(crate) sha256: [u8; 32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Difference {
+    Added,
+    Removed,
+    Modified,
+    /// Same content with a different modification time
+    MetadataOnly,
+}
+
+#[derive(Debug, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+impl Side {
+    /// Files of the chest latest snapshot, or of the directory with their
+    /// content hashed
+    pub(crate) fn files(&self) -> ChestResult<Vec<FileState>> {
+        match self {
+            Side::Chest(chest) => chest
+                .snapshot_files(None)?
+                .into_iter()
+                .map(|f| {
+                    Ok(FileState {
+                        name: f.metadata.filename.clone(),
+                        size_bytes: f.metadata.size_bytes,
+                        modified: f.metadata.modified,
+                        sha256: chest.content_sha256(f)?,
+                    })
+                })
+                .collect(),
+            Side::Dir(dir) => walk_dir(dir)?
+                .into_iter()
+                .map(|entry| {
+                    let payload = fs::read(&entry.path)?;
+                    let modified = fs::metadata(&entry.path)?
+                        .modified()
+                        .ok()
+                        .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs());
+                    Ok(FileState {
+                        name: entry.name,
+                        size_bytes: payload.len() as u64,
+                        modified,
+                        sha256: sha256(&payload),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Content of the file, decrypted in memory for chests
+    pub(crate) fn read(&self, name: &str) -> ChestResult<Vec<u8>> {
+        match self {
+            Side::Chest(chest) => {
+                let mut payload = Vec::new();
+                chest.decrypt_file_to_writer(chest.find_file(name)?, &mut payload)?;
+                Ok(payload)
+            }
+            Side::Dir(dir) => Ok(fs::read(dir.join(name))?),
+        }
+    }
+}
+
+/// Differences from the old files to the new ones, sorted by file name
+pub(crate) fn compare(old: &[FileState], new: &[FileState]) -> Vec<(Difference, String)> {
+    let mut differences = old
+        .iter()
+        .filter_map(|o| match new.iter().find(|n| n.name == o.name) {
+            None => Some((Difference::Removed, o.name.clone())),
+            Some(n) if n.size_bytes != o.size_bytes || n.sha256 != o.sha256 => {
+                Some((Difference::Modified, o.name.clone()))
+            }
+            Some(n) if n.modified != o.modified => Some((Difference::MetadataOnly, o.name.clone())),
+            Some(_) => None,
+        })
+        .chain(
+            new.iter()
+                .filter(|n| !old.iter().any(|o| o.name == n.name))
+                .map(|n| (Difference::Added, n.name.clone())),
+        )
+        .collect::<Vec<_>>();
+    differences.sort_by(|a, b| a.1.cmp(&b.1));
+    differences
+}
+
+/// Unified diff of two text files, `None` when either is binary or they are
+/// too large to compare
+pub(crate) fn text_diff(old: &[u8], new: &[u8]) -> Option<Vec<String>> {
+    let old = as_text(old)?.lines().collect::<Vec<_>>();
+    let new = as_text(new)?.lines().collect::<Vec<_>>();
+    let lines = diff_lines(&old, &new)?;
+    Some(render_unified(&lines))
+}
+
+fn as_text(payload: &[u8]) -> Option<&str> {
+    match payload.contains(&0) {
+        true => None,
+        false => std::str::from_utf8(payload).ok(),
+    }
+}
+
+/// Longest common subsequence of lines, after trimming the common prefix and
+/// suffix
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<Line<'a>>> {
+    let prefix = old.iter().zip(new).take_while(|(o, n)| o == n).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+    if (a.len() + 1) * (b.len() + 1) > MAX_DIFF_CELLS {
+        return None;
+    }
+    // lengths[i][j] is the LCS length of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut lengths = vec![0u32; (a.len() + 1) * width];
+    (0..a.len()).rev().for_each(|i| {
+        (0..b.len()).rev().for_each(|j| {
+            lengths[i * width + j] = match a[i] == b[j] {
+                true => lengths[(i + 1) * width + j + 1] + 1,
+                false => lengths[(i + 1) * width + j].max(lengths[i * width + j + 1]),
+            };
+        })
+    });
+    let mut lines = old[..prefix]
+        .iter()
+        .map(|l| Line::Same(l))
+        .collect::<Vec<_>>();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            lines.push(Line::Same(a[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if j < b.len()
+            && (i == a.len() || lengths[i * width + j + 1] > lengths[(i + 1) * width + j])
+        {
+            lines.push(Line::Added(b[j]));
+            j += 1;
+        } else {
+            lines.push(Line::Removed(a[i]));
+            i += 1;
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|l| Line::Same(l)));
+    Some(lines)
+}
+
+fn render_unified(lines: &[Line]) -> Vec<String> {
+    let changes = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| !matches!(l, Line::Same(_)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    // group changes close enough to share their context lines
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    changes.iter().for_each(|&i| {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + CONTEXT_LINES + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    });
+    hunks
+        .into_iter()
+        .flat_map(|(start, end)| {
+            let before = &lines[..start];
+            let hunk = &lines[start..end];
+            let old_start = before
+                .iter()
+                .filter(|l| !matches!(l, Line::Added(_)))
+                .count();
+            let new_start = before
+                .iter()
+                .filter(|l| !matches!(l, Line::Removed(_)))
+                .count();
+            let old_len = hunk.iter().filter(|l| !matches!(l, Line::Added(_))).count();
+            let new_len = hunk
+                .iter()
+                .filter(|l| !matches!(l, Line::Removed(_)))
+                .count();
+            std::iter::once(format!(
+                "@@ -{},{old_len} +{},{new_len} @@",
+                old_start + 1,
+                new_start + 1
+            ))
+            .chain(hunk.iter().map(|line| match line {
+                Line::Same(l) => format!(" {l}"),
+                Line::Removed(l) => format!("-{l}"),
+                Line::Added(l) => format!("+{l}"),
+            }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(name: &str, content: &[u8], modified: u64) -> FileState {
+        FileState {
+            name: name.to_string(),
+            size_bytes: content.len() as u64,
+            modified: Some(modified),
+            sha256: sha256(content),
+        }
+    }
+
+    #[test]
+    fn differences_are_classified() {
+        let old = [
+            state("kept", b"same", 1),
+            state("modified", b"old", 1),
+            state("removed", b"gone", 1),
+            state("touched", b"same", 1),
+        ];
+        let new = [
+            state("added", b"new", 2),
+            state("kept", b"same", 1),
+            state("modified", b"new", 1),
+            state("touched", b"same", 2),
+        ];
+        assert_eq!(
+            vec![
+                (Difference::Added, "added".to_string()),
+                (Difference::Modified, "modified".to_string()),
+                (Difference::Removed, "removed".to_string()),
+                (Difference::MetadataOnly, "touched".to_string()),
+            ],
+            compare(&old, &new)
+        );
+    }
+
+    #[test]
+    fn text_diff_is_unified() {
+        let old = b"a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let new = b"a\nb\nc\nd\nE\nf\ng\nh\ni\nj\n";
+        assert_eq!(
+            Some(vec![
+                "@@ -2,8 +2,9 @@".to_string(),
+                " b".to_string(),
+                " c".to_string(),
+                " d".to_string(),
+                "-e".to_string(),
+                "+E".to_string(),
+                " f".to_string(),
+                " g".to_string(),
+                " h".to_string(),
+                " i".to_string(),
+                "+j".to_string(),
+            ]),
+            text_diff(old, new)
+        );
+    }
+
+    #[test]
+    fn binary_files_have_no_text_diff() {
+        assert_eq!(None, text_diff(b"\x00\x01", b"text"));
+    }
+}
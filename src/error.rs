@@ -1,5 +1,10 @@
+use std::ops::RangeInclusive;
+
 use thiserror::Error;
 
+use crate::chest::CompressionAlgorithm;
+use crate::container::Damage;
+
 pub(crate) type ChestResult<T> = Result<T, ChestError>;
 
 #[derive(Debug, Error)]
@@ -10,8 +15,87 @@ pub(crate) enum ChestError {
     Serialization(#[from] bincode::Error),
     #[error("Couldn't compress or decompress: {0}")]
     Compress(#[from] CompressError),
-    #[error("Couldn't compress or decompress: {0}")]
+    #[error("Couldn't encrypt or decrypt: {0}")]
     Encrypt(#[from] EncryptError),
+    #[error("Invalid file pattern: {0}")]
+    Pattern(#[from] glob::PatternError),
+    #[error("No file in the chest matches the given patterns")]
+    NoMatchingFiles,
+    #[error("No file named {0} in the chest")]
+    FileNotFound(String),
+    #[error("Invalid file name {0}, it must be a relative file path without '..'")]
+    InvalidFilename(String),
+    #[error("Invalid use of stdin: {0}")]
+    Stdin(&'static str),
+    #[error("A chunk of the file is missing or empty, the chest is corrupted")]
+    MissingChunk,
+    #[error("No snapshot {0} in the chest")]
+    SnapshotNotFound(usize),
+    #[error("The chest has snapshots, record a new one with chest snapshot instead")]
+    HasSnapshots,
+    #[error("{} file(s) failed verification: {}", .0.len(), .0.join(", "))]
+    VerificationFailed(Vec<String>),
+    #[error("The chest is damaged: {}", .0.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", "))]
+    Damaged(Vec<Damage>),
+    #[error("Unsupported chest format version {0}, it was written by a newer chest")]
+    UnsupportedVersion(u16),
+    #[error("The chest is too damaged to be repaired: {}", .0.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", "))]
+    Unrepairable(Vec<Damage>),
+    #[error("A chest already exists at {0}, use --force to overwrite it")]
+    ChestExists(String),
+    #[error("The chest is locked by {}, try again later or wait longer with --lock-timeout", .0.map_or("another process".to_string(), |pid| format!("PID {pid}")))]
+    Locked(Option<u32>),
+    #[error("Invalid use of hidden files: {0}")]
+    Hidden(&'static str),
+    #[error(
+        "No decoy is large enough to hide {0}, create the chest with more decoys or larger files"
+    )]
+    NoDecoySpace(String),
+    #[error("Couldn't read the password: {0}")]
+    Password(String),
+    #[error("The password can't be empty")]
+    EmptyPassword,
+    #[error("The passwords don't match")]
+    PasswordMismatch,
+    #[error("The password is too weak, {warning} (score {score}, at least {min_score} required), use a longer passphrase or --allow-weak")]
+    WeakPassword {
+        score: u8,
+        min_score: u8,
+        warning: &'static str,
+    },
+}
+
+impl ChestError {
+    /// Stable machine readable error code, part of the JSON output schema
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            ChestError::Io(_) => "io",
+            ChestError::Serialization(_) => "serialization",
+            ChestError::Compress(CompressError::Unsupported(_)) => "unsupported_algorithm",
+            ChestError::Compress(_) => "compression",
+            ChestError::Encrypt(_) => "encryption",
+            ChestError::Pattern(_) => "invalid_pattern",
+            ChestError::NoMatchingFiles => "no_matching_files",
+            ChestError::FileNotFound(_) => "file_not_found",
+            ChestError::InvalidFilename(_) => "invalid_filename",
+            ChestError::Stdin(_) => "invalid_stdin",
+            ChestError::MissingChunk => "missing_chunk",
+            ChestError::SnapshotNotFound(_) => "snapshot_not_found",
+            ChestError::HasSnapshots => "has_snapshots",
+            ChestError::VerificationFailed(_) => "verification_failed",
+            ChestError::Damaged(_) => "damaged",
+            ChestError::UnsupportedVersion(_) => "unsupported_version",
+            ChestError::Unrepairable(_) => "unrepairable",
+            ChestError::ChestExists(_) => "chest_exists",
+            ChestError::Locked(_) => "locked",
+            ChestError::Hidden(_) => "invalid_hidden",
+            ChestError::NoDecoySpace(_) => "no_decoy_space",
+            ChestError::Password(_) => "password_source",
+            ChestError::EmptyPassword => "empty_password",
+            ChestError::PasswordMismatch => "password_mismatch",
+            ChestError::WeakPassword { .. } => "weak_password",
+        }
+    }
 }
 
 pub(crate) type CompressResult<T> = Result<T, CompressError>;
@@ -20,6 +104,20 @@ pub(crate) type CompressResult<T> = Result<T, CompressError>;
 pub(crate) enum CompressError {
     #[error("IO error")]
     Io(#[from] std::io::Error),
+    #[error("Invalid {algorithm:?} level {level}, expected {}-{}", levels.start(), levels.end())]
+    InvalidLevel {
+        algorithm: CompressionAlgorithm,
+        level: i32,
+        levels: RangeInclusive<i32>,
+    },
+    #[error("{0:?} support was not enabled when building chest")]
+    Unsupported(CompressionAlgorithm),
+    #[error("Couldn't train a compression dictionary: {0}")]
+    Dictionary(std::io::Error),
+    #[error("{0:?} doesn't support compression dictionaries, use --compression zstd")]
+    DictionaryUnsupported(CompressionAlgorithm),
+    #[error("The chest compression dictionary is missing")]
+    MissingDictionary,
 }
 
 pub(crate) type EncryptResult<T> = Result<T, EncryptError>;
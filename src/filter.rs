@@ -0,0 +1,85 @@
+use glob::{MatchOptions, Pattern};
+
+use crate::error::ChestResult;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Default)]
+pub(crate) struct FileFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl FileFilter {
+    pub(crate) fn new<S: AsRef<str>>(include: &[S], exclude: &[S]) -> ChestResult<Self> {
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub(crate) fn matches(&self, filename: &str) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|p| p.matches_with(filename, MATCH_OPTIONS));
+        included
+            && !self
+                .exclude
+                .iter()
+                .any(|p| p.matches_with(filename, MATCH_OPTIONS))
+    }
+}
+
+fn compile<S: AsRef<str>>(patterns: &[S]) -> ChestResult<Vec<Pattern>> {
+    Ok(patterns
+        .iter()
+        .map(|p| Pattern::new(p.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = FileFilter::default();
+        assert!(filter.matches("anything.txt"));
+    }
+
+    #[test]
+    fn exact_name_matches_only_that_file() {
+        let filter = FileFilter::new(&["db.env"], &[]).unwrap();
+        assert!(filter.matches("db.env"));
+        assert!(!filter.matches("app.env"));
+    }
+
+    #[test]
+    fn glob_does_not_cross_directories() {
+        let filter = FileFilter::new(&["secrets/*.env"], &[]).unwrap();
+        assert!(filter.matches("secrets/db.env"));
+        assert!(!filter.matches("secrets/nested/db.env"));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = FileFilter::new(&["*.env"], &["prod.*"]).unwrap();
+        assert!(filter.matches("dev.env"));
+        assert!(!filter.matches("prod.env"));
+    }
+
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        assert!(FileFilter::new(&["[unclosed"], &[]).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Deserialize;
+
+use crate::chest::{
+    CompressionAlgorithm, EncryptionAlgorithm, FileCompression, KeyDerivationAlgorithm, LockedFile,
+    Metadata, Public,
+};
+use crate::compression::default_level;
+use crate::error::ChestResult;
+
+/// Chest written before the payload had a format version
+#[derive(Deserialize)]
+struct FirstChest {
+    public: FirstPublic,
+    files: Vec<LockedFile>,
+}
+
+#[derive(Deserialize)]
+struct FirstPublic {
+    compression_algorithm: Option<CompressionAlgorithm>,
+    key_derivation_algorithm: KeyDerivationAlgorithm,
+    key_derivation_salt: Vec<u8>,
+    encryption_algorithm: EncryptionAlgorithm,
+}
+
+#[derive(Deserialize)]
+struct FirstMetadata {
+    filename: String,
+    size_bytes: u64,
+}
+
+/// Public part and files of a chest in the first format, `None` if the
+/// payload isn't one
+pub(crate) fn read(body: &[u8]) -> Option<(Public, Vec<LockedFile>)> {
+    let mut reader = body;
+    let chest: FirstChest = bincode::deserialize_from(&mut reader).ok()?;
+    if !reader.is_empty() {
+        return None;
+    }
+    let public = Public {
+        compression_level: chest
+            .public
+            .compression_algorithm
+            .as_ref()
+            .map(default_level),
+        compression_algorithm: chest.public.compression_algorithm,
+        key_derivation_algorithm: chest.public.key_derivation_algorithm,
+        key_derivation_salt: chest.public.key_derivation_salt,
+        encryption_algorithm: chest.public.encryption_algorithm,
+        ..Public::default()
+    };
+    Some((public, chest.files))
+}
+
+/// Metadata of a file in the first format, its hash being left unset so
+/// listing it doesn't decrypt its content
+pub(crate) fn metadata(metadata: &[u8], public: &Public) -> ChestResult<Metadata> {
+    let metadata: FirstMetadata = bincode::deserialize(metadata)?;
+    Ok(Metadata {
+        filename: metadata.filename,
+        size_bytes: metadata.size_bytes,
+        modified: None,
+        sha256: None,
+        compression: public
+            .compression_algorithm
+            .as_ref()
+            .map(|algorithm| FileCompression {
+                algorithm: algorithm.clone(),
+                level: default_level(algorithm),
+                dictionary: false,
+            }),
+    })
+}
@@ -0,0 +1,216 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::ValueEnum;
+
+use crate::chest::{Content, FileCompression, UnlockedFile};
+
+const SIZE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+const HASH_DISPLAY_LENGTH: usize = 12;
+
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub(crate) enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Stored,
+    Ratio,
+    Date,
+}
+
+pub(crate) fn sort_files(files: &mut [&UnlockedFile], key: SortKey, reverse: bool) {
+    files.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Name => a.metadata.filename.cmp(&b.metadata.filename),
+            SortKey::Size => a.metadata.size_bytes.cmp(&b.metadata.size_bytes),
+            SortKey::Stored => a.stored_bytes().cmp(&b.stored_bytes()),
+            SortKey::Ratio => ratio(a).partial_cmp(&ratio(b)).unwrap_or(Ordering::Equal),
+            SortKey::Date => a.metadata.modified.cmp(&b.metadata.modified),
+        };
+        ordering.then_with(|| a.metadata.filename.cmp(&b.metadata.filename))
+    });
+    if reverse {
+        files.reverse();
+    }
+}
+
+pub(crate) fn render_table(files: &[&UnlockedFile]) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:>10}  {:>10}  {:>6}  {:<12}  {:<20}  {:<12}  NAME",
+        "SIZE", "STORED", "RATIO", "METHOD", "MODIFIED", "SHA256"
+    )];
+    lines.extend(files.iter().map(|f| {
+        format!(
+            "{:>10}  {:>10}  {:>6}  {:<12}  {:<20}  {:<12}  {}",
+            format_size(f.metadata.size_bytes),
+            f.stored_bytes()
+                .map_or_else(|| "-".to_string(), format_size),
+            f.stored_bytes().map_or_else(
+                || "-".to_string(),
+                |stored| format_ratio(f.metadata.size_bytes, stored)
+            ),
+            format_method(f),
+            format_time(f.metadata.modified),
+            f.metadata.sha256.map_or_else(
+                || "-".to_string(),
+                |sha256| to_hex(&sha256)[..HASH_DISPLAY_LENGTH].to_string()
+            ),
+            f.metadata.filename
+        )
+    }));
+    lines
+}
+
+pub(crate) fn render_totals(files: &[&UnlockedFile], stored: u64) -> String {
+    let size = files.iter().map(|f| f.metadata.size_bytes).sum::<u64>();
+    format!(
+        "{} file(s), {} stored in {} ({})",
+        files.len(),
+        format_size(size),
+        format_size(stored),
+        format_ratio(size, stored)
+    )
+}
+
+#[derive(Default)]
+struct TreeNode<'a> {
+    children: BTreeMap<&'a str, TreeNode<'a>>,
+    file: Option<&'a UnlockedFile>,
+}
+
+pub(crate) fn render_tree(files: &[&UnlockedFile]) -> Vec<String> {
+    let mut root = TreeNode::default();
+    files.iter().for_each(|f| {
+        let node = f
+            .metadata
+            .filename
+            .split('/')
+            .filter(|part| !part.is_empty() && *part != ".")
+            .fold(&mut root, |node, part| {
+                node.children.entry(part).or_default()
+            });
+        node.file = Some(f);
+    });
+    let mut lines = vec![".".to_string()];
+    render_children(&root, "", &mut lines);
+    lines
+}
+
+fn render_children(node: &TreeNode, prefix: &str, lines: &mut Vec<String>) {
+    let count = node.children.len();
+    node.children
+        .iter()
+        .enumerate()
+        .for_each(|(i, (name, child))| {
+            let is_last = i + 1 == count;
+            let branch = if is_last { "└── " } else { "├── " };
+            let label = match child.file {
+                Some(f) => format!("{name} ({})", format_size(f.metadata.size_bytes)),
+                None => format!("{name}/"),
+            };
+            lines.push(format!("{prefix}{branch}{label}"));
+            let child_prefix = format!("{prefix}{}", if is_last { "    " } else { "│   " });
+            render_children(child, &child_prefix, lines);
+        });
+}
+
+pub(crate) fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} {}", SIZE_UNITS[0]),
+        _ => format!("{size:.1} {}", SIZE_UNITS[unit]),
+    }
+}
+
+pub(crate) fn format_ratio(size: u64, stored: u64) -> String {
+    match size {
+        0 => "-".to_string(),
+        _ => format!("{:.0}%", stored as f64 / size as f64 * 100.0),
+    }
+}
+
+pub(crate) fn format_time(secs: Option<u64>) -> String {
+    secs.map_or_else(
+        || "-".to_string(),
+        |secs| {
+            humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs)).to_string()
+        },
+    )
+}
+
+/// Compression of the file, or its layout when not compressed on its own
+pub(crate) fn format_method(file: &UnlockedFile) -> String {
+    match file.content {
+        Content::Solid(_) => "solid".to_string(),
+        Content::Chunks(_) => "dedup".to_string(),
+        Content::Blob(_) => format_compression(file.metadata.compression.as_ref()),
+    }
+}
+
+pub(crate) fn format_compression(compression: Option<&FileCompression>) -> String {
+    compression.map_or_else(
+        || "none".to_string(),
+        |c| {
+            let dictionary = if c.dictionary { "+dict" } else { "" };
+            format!("{:?}:{}{dictionary}", c.algorithm, c.level).to_lowercase()
+        },
+    )
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn ratio(file: &UnlockedFile) -> Option<f64> {
+    let stored = file.stored_bytes()?;
+    match file.metadata.size_bytes {
+        0 => Some(0.0),
+        size => Some(stored as f64 / size as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!("512 B", format_size(512));
+        assert_eq!("1.5 KiB", format_size(1536));
+        assert_eq!("3.0 GiB", format_size(3 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn ratio_of_empty_file_is_not_a_number() {
+        assert_eq!("-", format_ratio(0, 28));
+        assert_eq!("50%", format_ratio(200, 100));
+    }
+
+    #[test]
+    fn times_are_rfc3339() {
+        assert_eq!("1970-01-02T00:00:00Z", format_time(Some(86_400)));
+        assert_eq!("-", format_time(None));
+    }
+
+    #[test]
+    fn compression_is_algorithm_and_level() {
+        let compression = FileCompression {
+            algorithm: crate::chest::CompressionAlgorithm::Zstd,
+            level: 19,
+            dictionary: true,
+        };
+        assert_eq!("zstd:19+dict", format_compression(Some(&compression)));
+        assert_eq!("none", format_compression(None));
+    }
+
+    #[test]
+    fn hex_is_lowercase_and_padded() {
+        assert_eq!("00ff0a", to_hex(&[0, 255, 10]));
+    }
+}
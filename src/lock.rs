@@ -0,0 +1,121 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fs2::FileExt;
+
+use crate::error::{ChestError, ChestResult};
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Advisory lock on a chest, released when dropped
+///
+/// The lock is taken on a `name.chest.lock` file next to the chest rather
+/// than the chest itself, as chests are replaced by renaming a new file over
+/// them. It only guards against other `chest` processes. The lock file is
+/// created by the first command changing the chest and then left in place,
+/// removing it would let a process lock a file another one just unlinked.
+pub(crate) struct ChestLock {
+    // `None` when the chest folder is read-only, nothing can write to it then
+    _file: Option<fs::File>,
+}
+
+pub(crate) fn lock_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".lock");
+    name.into()
+}
+
+/// Lock for reading the chest, any number of processes can hold it at once
+///
+/// No lock file is created for it: without one no process is changing the
+/// chest, and one starting now can't be seen half written as chests are
+/// replaced atomically.
+pub(crate) fn shared(path: &Path, timeout: Duration) -> ChestResult<ChestLock> {
+    lock(path, timeout, false)
+}
+
+/// Lock for changing the chest, held by a single process at a time
+pub(crate) fn exclusive(path: &Path, timeout: Duration) -> ChestResult<ChestLock> {
+    lock(path, timeout, true)
+}
+
+fn lock(path: &Path, timeout: Duration, exclusive: bool) -> ChestResult<ChestLock> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(exclusive)
+        .truncate(false)
+        .open(lock_path(path));
+    let mut file = match file {
+        Ok(file) => file,
+        Err(e) if !exclusive && (is_read_only(&e) || e.kind() == io::ErrorKind::NotFound) => {
+            return Ok(ChestLock { _file: None });
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let start = Instant::now();
+    loop {
+        let locked = match exclusive {
+            true => FileExt::try_lock_exclusive(&file),
+            false => FileExt::try_lock_shared(&file),
+        };
+        match locked {
+            Ok(()) => break,
+            Err(e) if e.kind() != fs2::lock_contended_error().kind() => return Err(e.into()),
+            Err(_) if start.elapsed() >= timeout => {
+                return Err(ChestError::Locked(holder(&mut file)));
+            }
+            Err(_) => thread::sleep(RETRY_INTERVAL),
+        }
+    }
+    // record who holds the lock for the processes waiting on it
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(std::process::id().to_string().as_bytes())?;
+    Ok(ChestLock { _file: Some(file) })
+}
+
+/// PID recorded by the last process that took the lock
+fn holder(file: &mut fs::File) -> Option<u32> {
+    let mut pid = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+fn is_read_only(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive_lock_excludes_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.chest");
+        let timeout = Duration::from_millis(200);
+        drop(shared(&path, timeout).unwrap());
+        assert!(!lock_path(&path).exists());
+        drop(exclusive(&path, timeout).unwrap());
+        let reader = shared(&path, timeout).unwrap();
+        let other_reader = shared(&path, timeout).unwrap();
+        assert!(matches!(
+            exclusive(&path, timeout),
+            Err(ChestError::Locked(Some(pid))) if pid == std::process::id()
+        ));
+        drop((reader, other_reader));
+        let writer = exclusive(&path, timeout).unwrap();
+        assert!(shared(&path, timeout).is_err());
+        drop(writer);
+        assert!(shared(&path, timeout).is_ok());
+    }
+}
@@ -1,55 +1,185 @@
-use std::{ffi::OsStr, path::Path};
+use std::{ffi::OsStr, fs, io, path::Path, time::SystemTime};
 
-use chest::{LockedChest, UnlockedChest};
+use chest::{CompressionAlgorithm, Layout, LockedChest, UnlockedChest, UnlockedFile};
 use clap::Parser;
-use error::ChestResult;
-use term::{fatal, info, prompt, remove_last_lines, success, INFO};
+use conflict::Overwrite;
+use diff::Difference;
+use error::{ChestError, ChestResult, CompressError};
+use filter::FileFilter;
+use padding::Padding;
+use password::PasswordArgs;
+use report::{
+    CatReport, CheckReport, DiffEntryReport, DiffReport, HideReport, LogEntryReport, LogReport,
+    NewReport, OpenReport, PeekReport, RenamedReport, RepairReport, SalvageReport, SnapshotReport,
+    SyncReport, VerifyReport,
+};
+use sync::Change;
+use term::{confirm, fatal, info, print, remove_last_lines, success, warning, INFO};
 
+mod adaptive;
+mod atomic;
 mod chest;
 mod cli;
 mod compression;
+mod conflict;
+mod container;
 mod crypto;
+mod dedup;
+mod diff;
 mod error;
+mod filter;
 mod key;
+mod legacy;
+mod listing;
+mod lock;
+mod padding;
+mod password;
 mod random;
+mod report;
+mod salvage;
+mod strength;
+mod sync;
 mod term;
+mod verify;
+mod walk;
 
 fn main() {
-    if let Err(e) = run() {
-        fatal(&e.to_string(), 1);
+    let cmd = cli::Cli::parse();
+    let data_on_stdout = matches!(cmd.command, cli::Commands::Cat { .. });
+    term::init(cmd.output, data_on_stdout);
+    if let Err(e) = run(cmd) {
+        fatal(&e.to_string(), e.code(), 1);
     }
 }
 
-fn run() -> ChestResult<()> {
-    let cmd = cli::Cli::parse();
+fn run(cmd: cli::Cli) -> ChestResult<()> {
+    let timeout = cmd.lock_timeout;
     match cmd.command {
         cli::Commands::New {
             name,
             password,
             add,
+            stdin_name,
+            compression,
+            level,
             no_compression,
+            compress_rules,
+            dictionary,
+            dictionary_size,
+            solid,
+            dedup,
+            redundancy,
+            pad,
+            decoys,
+            force,
+            backup,
+            strength,
         } => {
-            let password = password.unwrap_or_else(|| prompt("Password"));
-            let mut unlocked = UnlockedChest::new(&password, !no_compression);
+            let stdin_count = add.iter().filter(|p| is_stdin(p)).count();
+            if stdin_count > 1 {
+                return Err(ChestError::Stdin("- can only be added once"));
+            }
+            if stdin_count == 1 && stdin_name.is_none() {
+                return Err(ChestError::Stdin("--name is required with --add -"));
+            }
+            if stdin_count == 0 && stdin_name.is_some() {
+                return Err(ChestError::Stdin("--name can only be used with --add -"));
+            }
+            if stdin_count == 1 && password.password_fd == Some(0) {
+                return Err(ChestError::Stdin(
+                    "--password-fd 0 can't be used with --add -",
+                ));
+            }
+            let compression = match (compression, dictionary) {
+                (Some(algorithm), _) => algorithm,
+                (None, true) => CompressionAlgorithm::Zstd,
+                (None, false) => CompressionAlgorithm::default(),
+            };
+            if dictionary && compression != CompressionAlgorithm::Zstd {
+                return Err(CompressError::DictionaryUnsupported(compression).into());
+            }
+            let path = format!("./{name}.chest");
+            // held from the existence check on, so two runs can't both create it
+            let _lock = lock::exclusive(Path::new(&path), timeout)?;
+            if !force && Path::new(&path).exists() {
+                return Err(ChestError::ChestExists(path));
+            }
+            let password = password.read_new("Password", &strength)?;
+            let compression = (!no_compression).then_some(compression);
+            let layout = match (solid, dedup) {
+                (true, _) => Layout::Solid,
+                (_, true) => Layout::Dedup,
+                _ => Layout::Blobs,
+            };
+            let mut unlocked = UnlockedChest::new(&password, compression, level, layout)?;
+            unlocked.set_compression_rules(compress_rules);
+            unlocked.redundancy = redundancy;
+            unlocked.padding = pad.then(|| Padding {
+                new_decoys: decoys.unwrap_or_default(),
+                ..Padding::default()
+            });
             success("Created new chest");
+            if dictionary {
+                info("Training compression dictionary");
+                let samples = add
+                    .iter()
+                    .filter(|path| !is_stdin(path))
+                    .map(fs::read)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let trained = unlocked.train_dictionary(&samples, dictionary_size);
+                remove_last_lines(1);
+                match trained {
+                    Ok(()) => success("Trained compression dictionary"),
+                    // too few or too small files, zstd still compresses them
+                    Err(ChestError::Compress(CompressError::Dictionary(e))) => warning(&format!(
+                        "Compressing without a dictionary, the files can't train one: {e}"
+                    )),
+                    Err(e) => return Err(e),
+                }
+            }
             add.iter().try_for_each::<_, ChestResult<()>>(|path| {
+                if is_stdin(path) {
+                    let name = stdin_name.clone().unwrap_or_default();
+                    info(&format!("Adding file {} from stdin", INFO.apply_to(&name)));
+                    unlocked.add_file_from_reader(
+                        io::stdin().lock(),
+                        name.clone(),
+                        Some(SystemTime::now()),
+                    )?;
+                    remove_last_lines(1);
+                    success(&format!("Added file {} from stdin", INFO.apply_to(&name)));
+                    return Ok(());
+                }
                 info(&format!("Adding file {}", INFO.apply_to(format_path(path))));
                 unlocked.add_file_from_path(path)?;
                 remove_last_lines(1);
                 success(&format!("Added file {}", INFO.apply_to(format_path(path))));
                 Ok(())
             })?;
+            // names as stored, e.g. without the folders of the added paths
+            let files = unlocked
+                .files
+                .iter()
+                .map(|f| f.metadata.filename.clone())
+                .collect();
             let locked = unlocked.lock(&password)?;
             success("Locked chest");
-            let path = format!("./{name}.chest");
             info(&format!("Writing chest to {}", INFO.apply_to(&path)));
-            locked.write_to_file(&path)?;
+            locked.write_to_file(&path, backup)?;
             remove_last_lines(1);
             success(&format!("Wrote chest to {}", INFO.apply_to(&path)));
+            term::result(&NewReport { path, files });
         }
 
-        cli::Commands::Peek { chest, password } => {
-            let password = password.unwrap_or_else(|| prompt("Password"));
+        cli::Commands::Peek {
+            chest,
+            sort,
+            reverse,
+            tree,
+            password,
+        } => {
+            let password = password.read("Password")?;
+            let _lock = lock::shared(&chest, timeout)?;
             let locked = LockedChest::from_file(chest)?;
             let unlocked = locked.unlock(&password)?;
             success("Unlocked chest");
@@ -59,36 +189,88 @@ fn run() -> ChestResult<()> {
                     unlocked
                         .public
                         .compression_algorithm
+                        .as_ref()
                         .map_or_else(|| "None".to_string(), |a| format!("{a:?}"))
                 )
             ));
+            if let Some(level) = unlocked.public.compression_level {
+                info(&format!("Compression level: {}", INFO.apply_to(level)));
+            }
+            info(&format!(
+                "Layout: {}",
+                INFO.apply_to(format!("{:?}", unlocked.public.layout).to_lowercase())
+            ));
+            if let Some(dictionary) = &unlocked.dictionary {
+                info(&format!(
+                    "Compression dictionary: {}",
+                    INFO.apply_to(listing::format_size(dictionary.len() as u64))
+                ));
+            }
+            if let Some(percent) = unlocked.redundancy {
+                info(&format!(
+                    "Redundancy: {}",
+                    INFO.apply_to(format!("{percent}%"))
+                ));
+            }
+            if let Some(padding) = &unlocked.padding {
+                info(&format!(
+                    "Padding: {}, with {} decoy file(s)",
+                    INFO.apply_to("padmé"),
+                    INFO.apply_to(padding.decoy_count())
+                ));
+            }
             info(&format!(
                 "Key derivation algorithm: {:?}",
-                INFO.apply_to(unlocked.public.key_derivation_algorithm)
+                INFO.apply_to(&unlocked.public.key_derivation_algorithm)
             ));
             info(&format!(
                 "Encryption algorithm: {:?}",
-                INFO.apply_to(unlocked.public.encryption_algorithm)
+                INFO.apply_to(&unlocked.public.encryption_algorithm)
             ));
-            unlocked
-                .files
-                .iter()
-                .for_each(|f| info(&format!("File: {}", INFO.apply_to(&f.metadata.filename))));
+            if let Err(e) = unlocked.ensure_supported() {
+                warning(&format!("Files can't be extracted: {e}"));
+            }
+            if let Some(latest) = unlocked.snapshots.last() {
+                info(&format!(
+                    "Latest snapshot: {}, taken {}",
+                    INFO.apply_to(unlocked.snapshots.len()),
+                    INFO.apply_to(listing::format_time(Some(latest.created)))
+                ));
+            }
+            let mut files = unlocked.snapshot_files(None)?;
+            listing::sort_files(&mut files, sort, reverse);
+            let lines = match tree {
+                true => listing::render_tree(&files),
+                false => listing::render_table(&files),
+            };
+            lines.iter().for_each(|line| print(line));
+            info(&listing::render_totals(&files, unlocked.stored_bytes()));
+            if let Some(stats) = unlocked.dedup_stats() {
+                info(&format!(
+                    "Deduplication: {} unique chunk(s) out of {}, {} unique out of {}, saved {}",
+                    INFO.apply_to(stats.unique_chunks),
+                    INFO.apply_to(stats.chunks),
+                    INFO.apply_to(listing::format_size(stats.unique_bytes)),
+                    INFO.apply_to(listing::format_size(stats.referenced_bytes)),
+                    INFO.apply_to(listing::format_size(stats.saved_bytes())),
+                ));
+            }
+            term::result(&PeekReport::new(&unlocked, &files));
         }
 
         cli::Commands::Open {
             chest,
+            patterns,
+            exclude,
             out,
+            snapshot,
+            overwrite,
+            rename_on_conflict,
+            salvage,
             password,
         } => {
-            let password = password.unwrap_or_else(|| prompt("Password"));
-            let locked = LockedChest::from_file(&chest)?;
-            success(&format!(
-                "Opened chest {}",
-                INFO.apply_to(format_path(&chest))
-            ));
-            let unlocked = locked.unlock(&password)?;
-            success("Unlocked chest");
+            let filter = FileFilter::new(&patterns, &exclude)?;
+            let password = password.read("Password")?;
             let out = out.unwrap_or_else(|| {
                 chest
                     .as_path()
@@ -96,21 +278,515 @@ fn run() -> ChestResult<()> {
                     .unwrap_or(OsStr::new("out"))
                     .into()
             });
+            let _lock = lock::shared(&chest, timeout)?;
+            if salvage {
+                let (locked, mut damage) = salvage::read_chest(&fs::read(&chest)?)?;
+                damage.iter().for_each(|d| warning(d));
+                let (unlocked, mut lost_parts) = locked.unlock_salvaging(&password)?;
+                lost_parts
+                    .iter()
+                    .for_each(|l| warning(&format!("Lost {l}")));
+                success("Unlocked chest");
+                let (files, mut lost) =
+                    salvage::valid_files(unlocked.matching_files(&filter, snapshot)?);
+                let plan = plan_extraction(&files, &out, overwrite, rename_on_conflict)?;
+                fs::create_dir_all(&out)?;
+                let (files, mut corrupted) = salvage::extract(&unlocked, &plan.writes)?;
+                lost.append(&mut corrupted);
+                lost.iter()
+                    .for_each(|l| warning(&format!("Lost {}: {}", l.name, l.reason)));
+                success(&format!(
+                    "Salvaged {} file(s) to folder {}, lost {}",
+                    INFO.apply_to(files.len()),
+                    INFO.apply_to(format_path(&out)),
+                    INFO.apply_to(lost.len())
+                ));
+                damage.append(&mut lost_parts);
+                term::result(&SalvageReport {
+                    out: format_path(&out),
+                    files,
+                    renamed: RenamedReport::from_plan(&plan),
+                    skipped: plan.skipped,
+                    lost,
+                    damage,
+                });
+                return Ok(());
+            }
+            let locked = LockedChest::from_file(&chest)?;
+            success(&format!(
+                "Opened chest {}",
+                INFO.apply_to(format_path(&chest))
+            ));
+            let unlocked = locked.unlock(&password)?;
+            unlocked.ensure_supported()?;
+            success("Unlocked chest");
+            let files = unlocked.matching_files(&filter, snapshot)?;
+            let plan = plan_extraction(&files, &out, overwrite, rename_on_conflict)?;
             info(&format!(
                 "Decrypting & extracting chest to folder {}",
                 INFO.apply_to(format_path(&out))
             ));
-            unlocked.decrypt_files_to_folder(&out)?;
+            fs::create_dir_all(&out)?;
+            unlocked.decrypt_files_to_paths(&plan.writes)?;
             remove_last_lines(1);
             success(&format!(
-                "Decrypted & extracted chest to folder {}",
+                "Decrypted & extracted {} file(s) to folder {}",
+                INFO.apply_to(plan.writes.len()),
                 INFO.apply_to(format_path(&out))
             ));
+            if !plan.skipped.is_empty() {
+                warning(&format!(
+                    "Skipped {} existing file(s), use --overwrite or --rename-on-conflict",
+                    plan.skipped.len()
+                ));
+            }
+            term::result(&OpenReport {
+                out: format_path(&out),
+                files: plan
+                    .writes
+                    .iter()
+                    .map(|(f, _)| f.metadata.filename.clone())
+                    .collect(),
+                renamed: RenamedReport::from_plan(&plan),
+                skipped: plan.skipped,
+            });
+        }
+
+        cli::Commands::Cat {
+            chest,
+            file,
+            password,
+        } => {
+            let password = password.read("Password")?;
+            let _lock = lock::shared(&chest, timeout)?;
+            let locked = LockedChest::from_file(&chest)?;
+            let unlocked = locked.unlock(&password)?;
+            unlocked.ensure_supported()?;
+            let file = unlocked.find_file(&file)?;
+            let size_bytes = unlocked.decrypt_file_to_writer(file, &mut io::stdout().lock())?;
+            term::result(&CatReport {
+                file: file.metadata.filename.clone(),
+                size_bytes,
+            });
+        }
+
+        cli::Commands::Verify { chest, password } => {
+            let password = password.read("Password")?;
+            let _lock = lock::shared(&chest, timeout)?;
+            let unlocked = LockedChest::from_file(&chest)?.unlock(&password)?;
+            unlocked.ensure_supported()?;
+            success("Unlocked chest");
+            let failures = unlocked
+                .files
+                .iter()
+                .filter_map(|f| {
+                    let name = &f.metadata.filename;
+                    info(&format!("Verifying file {}", INFO.apply_to(name)));
+                    let failure = verify::verify_file(&unlocked, f);
+                    remove_last_lines(1);
+                    match failure {
+                        Some(reason) => {
+                            warning(&format!("{}: {reason}", INFO.apply_to(name)));
+                            Some(name.clone())
+                        }
+                        None => {
+                            success(&format!("Verified file {}", INFO.apply_to(name)));
+                            None
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+            if !failures.is_empty() {
+                return Err(ChestError::VerificationFailed(failures));
+            }
+            success(&format!(
+                "Verified {} file(s)",
+                INFO.apply_to(unlocked.files.len())
+            ));
+            term::result(&VerifyReport {
+                files: unlocked.files.len(),
+            });
+        }
+
+        cli::Commands::Check { chest } => {
+            let _lock = lock::shared(&chest, timeout)?;
+            let payload = fs::read(&chest)?;
+            let checked = container::check(&payload)?;
+            if !checked.damage.is_empty() {
+                return Err(ChestError::Damaged(checked.damage));
+            }
+            match checked.blocks {
+                Some(blocks) => success(&format!(
+                    "No damage found in {} block(s) of {}{}",
+                    INFO.apply_to(blocks),
+                    INFO.apply_to(format_path(&chest)),
+                    checked
+                        .redundancy
+                        .map(|p| format!(", with {p}% redundancy"))
+                        .unwrap_or_default()
+                )),
+                None => {
+                    LockedChest::from_body(checked.body)?;
+                    warning(&format!(
+                        "{} was written without checksums, only its structure was checked",
+                        INFO.apply_to(format_path(&chest))
+                    ));
+                }
+            }
+            term::result(&CheckReport {
+                checksums: checked.blocks.is_some(),
+                redundancy_percent: checked.redundancy,
+                blocks: checked.blocks.unwrap_or_default(),
+                size_bytes: payload.len() as u64,
+            });
+        }
+
+        cli::Commands::Repair { chest, backup } => {
+            let _lock = lock::exclusive(&chest, timeout)?;
+            let payload = fs::read(&chest)?;
+            let damage = container::check(&payload)?.damage;
+            if damage.is_empty() {
+                success(&format!(
+                    "No damage found in {}, nothing to repair",
+                    INFO.apply_to(format_path(&chest))
+                ));
+            } else {
+                damage.iter().for_each(|d| warning(&format!("Damaged {d}")));
+                info("Repairing chest");
+                let repaired = container::repair(&payload)?;
+                atomic::write(&chest, &repaired, backup)?;
+                remove_last_lines(1);
+                success(&format!(
+                    "Repaired {} damaged region(s) of {}",
+                    INFO.apply_to(damage.len()),
+                    INFO.apply_to(format_path(&chest))
+                ));
+            }
+            term::result(&RepairReport {
+                repaired: damage.iter().map(|d| d.to_string()).collect(),
+            });
+        }
+
+        cli::Commands::Hide {
+            chest,
+            add,
+            backup,
+            password,
+            strength,
+            hidden_password,
+        } => {
+            let hidden_password = PasswordArgs::from(hidden_password);
+            // stdin can only give one of them
+            if password.password_fd == Some(0) && hidden_password.password_fd == Some(0) {
+                return Err(ChestError::Password(
+                    "--password-fd 0 and --hidden-password-fd 0 both read stdin".to_string(),
+                ));
+            }
+            let password = password.read("Password")?;
+            let hidden_password = hidden_password.read_new("Hidden password", &strength)?;
+            let _lock = lock::exclusive(&chest, timeout)?;
+            let mut unlocked = LockedChest::from_file(&chest)?.unlock(&password)?;
+            success("Unlocked chest");
+            let mut hidden = unlocked.new_hidden(&hidden_password)?;
+            add.iter().try_for_each::<_, ChestResult<()>>(|path| {
+                info(&format!("Adding file {}", INFO.apply_to(format_path(path))));
+                hidden.add_file_from_path(path)?;
+                remove_last_lines(1);
+                success(&format!("Added file {}", INFO.apply_to(format_path(path))));
+                Ok(())
+            })?;
+            let files = hidden
+                .files
+                .iter()
+                .map(|f| f.metadata.filename.clone())
+                .collect();
+            unlocked.hide(hidden)?;
+            let locked = unlocked.lock(&password)?;
+            success("Locked chest");
+            info(&format!(
+                "Writing chest to {}",
+                INFO.apply_to(format_path(&chest))
+            ));
+            locked.write_to_file(&chest, backup)?;
+            remove_last_lines(1);
+            success(&format!(
+                "Hid {} file(s) in {}",
+                INFO.apply_to(add.len()),
+                INFO.apply_to(format_path(&chest))
+            ));
+            term::result(&HideReport {
+                path: format_path(&chest),
+                files,
+            });
+        }
+
+        cli::Commands::Snapshot {
+            chest,
+            dir,
+            backup,
+            password,
+            strength,
+        } => {
+            let entries = walk::walk_dir(&dir)?;
+            let password = match chest.exists() {
+                true => password.read("Password")?,
+                false => password.read_new("Password", &strength)?,
+            };
+            let _lock = lock::exclusive(&chest, timeout)?;
+            let mut unlocked = match chest.exists() {
+                true => {
+                    let unlocked = LockedChest::from_file(&chest)?.unlock(&password)?;
+                    success("Unlocked chest");
+                    unlocked
+                }
+                false => {
+                    let unlocked = UnlockedChest::new(
+                        &password,
+                        Some(CompressionAlgorithm::default()),
+                        None,
+                        Layout::Blobs,
+                    )?;
+                    success("Created new chest");
+                    unlocked
+                }
+            };
+            let mut snapshot = unlocked.start_snapshot();
+            let mut stored_files = 0;
+            entries.iter().try_for_each::<_, ChestResult<()>>(|entry| {
+                info(&format!("Adding file {}", INFO.apply_to(&entry.name)));
+                let file = fs::File::open(&entry.path)?;
+                let modified = file.metadata()?.modified().ok();
+                let stored = unlocked.add_snapshot_file(
+                    &mut snapshot,
+                    file,
+                    entry.name.clone(),
+                    modified,
+                )?;
+                remove_last_lines(1);
+                if stored {
+                    stored_files += 1;
+                    success(&format!("Added file {}", INFO.apply_to(&entry.name)));
+                }
+                Ok(())
+            })?;
+            unlocked.push_snapshot(snapshot);
+            let number = unlocked.snapshots.len();
+            let locked = unlocked.lock(&password)?;
+            success("Locked chest");
+            info(&format!(
+                "Writing chest to {}",
+                INFO.apply_to(format_path(&chest))
+            ));
+            locked.write_to_file(&chest, backup)?;
+            remove_last_lines(1);
+            success(&format!(
+                "Recorded snapshot {} of {} file(s), {} new or changed, in {}",
+                INFO.apply_to(number),
+                INFO.apply_to(entries.len()),
+                INFO.apply_to(stored_files),
+                INFO.apply_to(format_path(&chest))
+            ));
+            term::result(&SnapshotReport {
+                path: format_path(&chest),
+                snapshot: number,
+                files: entries.len(),
+                stored_files,
+                shared_files: entries.len() - stored_files,
+            });
+        }
+
+        cli::Commands::Sync {
+            chest,
+            dir,
+            delete,
+            backup,
+            password,
+        } => {
+            let entries = walk::walk_dir(&dir)?;
+            let password = password.read("Password")?;
+            let _lock = lock::exclusive(&chest, timeout)?;
+            let mut unlocked = LockedChest::from_file(&chest)?.unlock(&password)?;
+            success("Unlocked chest");
+            if !unlocked.snapshots.is_empty() {
+                return Err(ChestError::HasSnapshots);
+            }
+            let mut report = SyncReport {
+                path: format_path(&chest),
+                added: Vec::new(),
+                replaced: Vec::new(),
+                deleted: Vec::new(),
+                unchanged: 0,
+            };
+            entries.iter().try_for_each::<_, ChestResult<()>>(|entry| {
+                info(&format!("Syncing file {}", INFO.apply_to(&entry.name)));
+                let change = sync::sync_entry(&mut unlocked, entry)?;
+                remove_last_lines(1);
+                match change {
+                    Change::Added => {
+                        success(&format!("Added file {}", INFO.apply_to(&entry.name)));
+                        report.added.push(entry.name.clone());
+                    }
+                    Change::Replaced => {
+                        success(&format!("Replaced file {}", INFO.apply_to(&entry.name)));
+                        report.replaced.push(entry.name.clone());
+                    }
+                    Change::Touched | Change::Unchanged => report.unchanged += 1,
+                }
+                Ok(())
+            })?;
+            if delete {
+                sync::removed(&unlocked, &entries)
+                    .into_iter()
+                    .try_for_each::<_, ChestResult<()>>(|name| {
+                        unlocked.remove_file(&name)?;
+                        success(&format!("Deleted file {}", INFO.apply_to(&name)));
+                        report.deleted.push(name);
+                        Ok(())
+                    })?;
+            }
+            let locked = unlocked.lock(&password)?;
+            success("Locked chest");
+            info(&format!(
+                "Writing chest to {}",
+                INFO.apply_to(format_path(&chest))
+            ));
+            locked.write_to_file(&chest, backup)?;
+            remove_last_lines(1);
+            success(&format!(
+                "Synced {}: {} added, {} replaced, {} deleted, {} unchanged",
+                INFO.apply_to(format_path(&chest)),
+                INFO.apply_to(report.added.len()),
+                INFO.apply_to(report.replaced.len()),
+                INFO.apply_to(report.deleted.len()),
+                INFO.apply_to(report.unchanged)
+            ));
+            term::result(&report);
+        }
+
+        cli::Commands::Diff {
+            chest,
+            target,
+            content,
+            password,
+            target_password,
+        } => {
+            let target_password = PasswordArgs::from(target_password);
+            if password.password_fd == Some(0) && target_password.password_fd == Some(0) {
+                return Err(ChestError::Password(
+                    "--password-fd 0 and --target-password-fd 0 both read stdin".to_string(),
+                ));
+            }
+            let password = password.read("Password")?;
+            let _lock = lock::shared(&chest, timeout)?;
+            let old =
+                diff::Side::Chest(Box::new(LockedChest::from_file(&chest)?.unlock(&password)?));
+            let new = match target.is_dir() {
+                true => diff::Side::Dir(target),
+                false => {
+                    let password = match target_password.is_given() {
+                        true => target_password.read("Target password")?,
+                        false => password,
+                    };
+                    let _lock = lock::shared(&target, timeout)?;
+                    diff::Side::Chest(Box::new(
+                        LockedChest::from_file(&target)?.unlock(&password)?,
+                    ))
+                }
+            };
+            success("Unlocked chest");
+            let differences = diff::compare(&old.files()?, &new.files()?);
+            let files = differences
+                .into_iter()
+                .map(|(difference, name)| {
+                    let content = match (content, difference) {
+                        (true, Difference::Modified) => {
+                            diff::text_diff(&old.read(&name)?, &new.read(&name)?)
+                        }
+                        _ => None,
+                    };
+                    Ok(DiffEntryReport {
+                        name,
+                        difference,
+                        content,
+                    })
+                })
+                .collect::<ChestResult<Vec<_>>>()?;
+            files.iter().for_each(|f| {
+                let status = match f.difference {
+                    Difference::Added => "A",
+                    Difference::Removed => "D",
+                    Difference::Modified => "M",
+                    Difference::MetadataOnly => "T",
+                };
+                print(&format!("{status} {}", f.name));
+                f.content.iter().flatten().for_each(|line| print(line));
+            });
+            info(&format!("{} difference(s)", INFO.apply_to(files.len())));
+            term::result(&DiffReport { files });
+        }
+
+        cli::Commands::Log { chest, password } => {
+            let password = password.read("Password")?;
+            let _lock = lock::shared(&chest, timeout)?;
+            let unlocked = LockedChest::from_file(&chest)?.unlock(&password)?;
+            success("Unlocked chest");
+            let snapshots = (1..=unlocked.snapshots.len())
+                .map(|number| LogEntryReport::new(&unlocked, number))
+                .collect::<Vec<_>>();
+            if snapshots.is_empty() {
+                info("No snapshot in the chest");
+            } else {
+                print(&format!(
+                    "{:>8}  {:<20}  {:>6}  {:>6}  SIZE",
+                    "SNAPSHOT", "CREATED", "FILES", "NEW"
+                ));
+            }
+            snapshots.iter().for_each(|s| {
+                print(&format!(
+                    "{:>8}  {:<20}  {:>6}  {:>6}  {}",
+                    s.snapshot,
+                    s.created,
+                    s.files,
+                    s.new_files,
+                    listing::format_size(s.size_bytes)
+                ))
+            });
+            term::result(&LogReport { snapshots });
         }
     };
     Ok(())
 }
 
+/// List every conflict with existing files, then decide what to do with
+/// each, prompting if asked to
+fn plan_extraction<'a>(
+    files: &[&'a UnlockedFile],
+    out: &Path,
+    overwrite: Overwrite,
+    rename: bool,
+) -> ChestResult<conflict::Plan<'a>> {
+    let conflicts = conflict::find(files, out)?;
+    conflicts.iter().for_each(|c| {
+        warning(&format!(
+            "File {} already exists{}",
+            INFO.apply_to(format_path(&c.path)),
+            if c.newer {
+                ", the chest one is newer"
+            } else {
+                ""
+            }
+        ))
+    });
+    Ok(conflict::plan(
+        files,
+        out,
+        &conflicts,
+        overwrite,
+        rename,
+        |c| confirm(&format!("Overwrite {}?", format_path(&c.path))),
+    ))
+}
+
 fn format_path(path: &Path) -> String {
     let path_string = path.to_str().unwrap_or_default().to_string();
     if path.is_absolute() {
@@ -119,3 +795,7 @@ fn format_path(path: &Path) -> String {
 
     format!("./{path_string}")
 }
+
+fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
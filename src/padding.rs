@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chest::{EncryptedBlob, Metadata};
+use crate::crypto::{Encrypt, NONCE_LENGTH, SALT_LENGTH, TAG_LENGTH};
+use crate::error::{ChestError, ChestResult, EncryptError};
+use crate::random::generate_random_bytes;
+
+// smallest encrypted index, so that small chests all look the same
+const MIN_INDEX_BYTES: usize = 1024;
+// room kept in the index slots for the index entry of a file hidden in each
+// decoy, whether files are hidden or not
+const HIDDEN_INDEX_BYTES_PER_DECOY: usize = 256;
+
+/// Privacy mode of a chest, hiding its file count and sizes
+#[derive(Clone, Default)]
+pub(crate) struct Padding {
+    /// Random blobs looking like files, kept as they are when the chest is
+    /// locked again as they may hold hidden files
+    pub(crate) decoys: Vec<EncryptedBlob>,
+    /// Number of decoys to add when the chest is locked
+    pub(crate) new_decoys: usize,
+    /// Second index slot, random or the index of the hidden files
+    pub(crate) hidden_index: Option<EncryptedBlob>,
+}
+
+impl Padding {
+    pub(crate) fn decoy_count(&self) -> usize {
+        self.decoys.len() + self.new_decoys
+    }
+}
+
+/// Encrypted index of a padded chest, replacing the per file metadata
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PaddedIndex {
+    /// Metadata of the real files, the files after them being decoys
+    pub(crate) metadata: Vec<Metadata>,
+    /// Length of every blob before padding, in the chest blobs order
+    pub(crate) lengths: Vec<u64>,
+}
+
+/// Index of the files hidden in the decoys of a padded chest
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct HiddenIndex {
+    pub(crate) metadata: Vec<Metadata>,
+    /// Position of the decoy holding each file, counted back from the end of
+    /// the chest files as decoys stay last
+    pub(crate) from_end: Vec<usize>,
+    /// Length of each file blob, the rest of its decoy being random
+    pub(crate) lengths: Vec<u64>,
+}
+
+/// Padmé length, at most 12% larger, so that only O(log log n) bits of the
+/// length are leaked
+pub(crate) fn padme(len: u64) -> u64 {
+    if len < 2 {
+        return len;
+    }
+    let exponent = len.ilog2();
+    let significant_bits = exponent.ilog2() + 1;
+    let mask = (1u64 << (exponent - significant_bits)) - 1;
+    (len + mask) & !mask
+}
+
+/// Pad the ciphertext with random bytes to its Padmé length, they can't be
+/// told apart from it, returning its length
+pub(crate) fn pad(blob: &mut EncryptedBlob) -> ChestResult<u64> {
+    let len = blob.cipher.len() as u64;
+    let padding = random((padme(len) - len) as usize)?;
+    blob.cipher.extend_from_slice(&padding);
+    Ok(len)
+}
+
+pub(crate) fn unpad(blob: &mut EncryptedBlob, len: u64) -> ChestResult<()> {
+    if len > blob.cipher.len() as u64 {
+        return Err(corrupted("padded blob is shorter than its length"));
+    }
+    blob.cipher.truncate(len as usize);
+    Ok(())
+}
+
+/// Append random bytes to the blob up to `len`
+pub(crate) fn fill(blob: &mut EncryptedBlob, len: usize) -> ChestResult<()> {
+    let padding = random(len.saturating_sub(blob.cipher.len()))?;
+    blob.cipher.extend_from_slice(&padding);
+    Ok(())
+}
+
+/// Encrypt an index zero padded so its ciphertext length is a power of two,
+/// its slot can then be filled with random bytes and the index still found
+/// without storing its length, bincode ignoring the trailing zeros
+pub(crate) fn seal_index(
+    encryptor: &impl Encrypt,
+    key: &[u8],
+    mut payload: Vec<u8>,
+) -> ChestResult<EncryptedBlob> {
+    let len = (payload.len() + TAG_LENGTH)
+        .max(MIN_INDEX_BYTES)
+        .next_power_of_two();
+    payload.resize(len - TAG_LENGTH, 0);
+    Ok(encryptor.encrypt(payload, &key.to_vec().try_into().unwrap())?)
+}
+
+/// Decrypt the index at the start of the slot, trying every power of two
+pub(crate) fn open_index(
+    encryptor: &impl Encrypt,
+    key: &[u8],
+    slot: &EncryptedBlob,
+) -> ChestResult<Vec<u8>> {
+    let mut result = Err(EncryptError::Crypto(ring::error::Unspecified));
+    let mut len = MIN_INDEX_BYTES;
+    while len <= slot.cipher.len() {
+        let index = EncryptedBlob {
+            cipher: slot.cipher[..len].to_vec(),
+            ..slot.clone()
+        };
+        result = encryptor.decrypt(&index, &key.to_vec().try_into().unwrap());
+        if result.is_ok() {
+            break;
+        }
+        len *= 2;
+    }
+    Ok(result?)
+}
+
+/// Length of both index slots, from the index length and decoy count only as
+/// the first password reveals them, so that it tells nothing of hidden files
+pub(crate) fn slot_len(index_len: usize, decoys: usize) -> usize {
+    index_len.max(hidden_index_room(decoys))
+}
+
+/// Largest sealed hidden index both slots always have room for
+pub(crate) fn hidden_index_room(decoys: usize) -> usize {
+    (decoys * HIDDEN_INDEX_BYTES_PER_DECOY)
+        .max(MIN_INDEX_BYTES)
+        .next_power_of_two()
+}
+
+/// Random blob of the given length, indistinguishable from an encrypted one
+pub(crate) fn decoy(len: usize) -> ChestResult<EncryptedBlob> {
+    Ok(EncryptedBlob {
+        cipher: random(len)?,
+        salt: random(SALT_LENGTH)?,
+        nonce: random(NONCE_LENGTH)?,
+    })
+}
+
+/// Padmé size picked log-uniformly between `min` and `max`, so that decoys
+/// aren't all sized like one of the files
+pub(crate) fn random_size(min: usize, max: usize) -> ChestResult<usize> {
+    let fraction = u32::from_le_bytes(random(4)?.try_into().unwrap()) as f64 / u32::MAX as f64;
+    let (min, max) = (min.max(1) as f64, max.max(min).max(1) as f64);
+    let size = (min * (max / min).powf(fraction)).round() as u64;
+    Ok(padme(size) as usize)
+}
+
+pub(crate) fn corrupted(msg: &str) -> ChestError {
+    ChestError::Serialization(Box::new(bincode::ErrorKind::Custom(msg.to_string())))
+}
+
+fn random(len: usize) -> ChestResult<Vec<u8>> {
+    Ok(generate_random_bytes(len).map_err(EncryptError::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padme_buckets() {
+        assert_eq!(0, padme(0));
+        assert_eq!(1, padme(1));
+        assert_eq!(10, padme(9));
+        assert_eq!(104, padme(100));
+        assert_eq!(1024, padme(1000));
+        (2..100_000).for_each(|len| {
+            let padded = padme(len);
+            assert!(padded >= len && padded - len <= len * 12 / 100 + 1);
+        });
+        // lengths in the same bucket can't be told apart
+        assert_eq!(padme(1_000_001), padme(1_015_000));
+    }
+
+    #[test]
+    fn index_is_found_in_a_larger_slot() {
+        let encryptor = crate::crypto::get_encryptor(&crate::chest::EncryptionAlgorithm::Aes256);
+        let key = [7u8; 32];
+        let mut slot = seal_index(&encryptor, &key, b"index".to_vec()).unwrap();
+        assert_eq!(MIN_INDEX_BYTES, slot.cipher.len());
+        fill(&mut slot, 8 * MIN_INDEX_BYTES).unwrap();
+        let index = open_index(&encryptor, &key, &slot).unwrap();
+        assert_eq!(b"index", &index[..5]);
+        assert!(open_index(&encryptor, &[8u8; 32], &slot).is_err());
+    }
+
+    #[test]
+    fn pad_roundtrip() {
+        let mut blob = decoy(1000).unwrap();
+        let cipher = blob.cipher.clone();
+        let len = pad(&mut blob).unwrap();
+        assert_eq!(1024, blob.cipher.len());
+        unpad(&mut blob, len).unwrap();
+        assert_eq!(cipher, blob.cipher);
+    }
+}
@@ -0,0 +1,344 @@
+use std::env;
+use std::fs;
+#[cfg(unix)]
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use clap::Args;
+use console::Term;
+
+use crate::error::{ChestError, ChestResult};
+use crate::strength;
+use crate::term::prompt;
+
+/// Where the chest password is read from, at most one source can be given
+/// and the password is prompted for when none is
+#[derive(Debug, Default, Args)]
+#[group(multiple = false)]
+pub(crate) struct PasswordArgs {
+    /// Chest password, visible to other users and kept in the shell history
+    #[clap(short, long)]
+    pub(crate) password: Option<String>,
+    /// Read the password from the first line of a file
+    #[clap(long, value_name = "PATH")]
+    pub(crate) password_file: Option<PathBuf>,
+    /// Read the password from an environment variable
+    #[clap(long, value_name = "VAR")]
+    pub(crate) password_env: Option<String>,
+    /// Read the password from the first line of an open file descriptor
+    #[clap(long, value_name = "N")]
+    pub(crate) password_fd: Option<u32>,
+    /// Read the password from the first line printed by a shell command
+    #[clap(long, value_name = "COMMAND")]
+    pub(crate) password_command: Option<String>,
+    /// Prefix of the options, for the ones of another password
+    #[clap(skip)]
+    prefix: &'static str,
+}
+
+/// Where the password of the hidden files is read from, with the same
+/// sources as the chest password
+#[derive(Debug, Args)]
+#[group(multiple = false)]
+pub(crate) struct HiddenPasswordArgs {
+    /// Password of the hidden files, visible to other users and kept in the
+    /// shell history
+    #[clap(long, value_name = "PASSWORD")]
+    hidden_password: Option<String>,
+    /// Read the hidden password from the first line of a file
+    #[clap(long, value_name = "PATH")]
+    hidden_password_file: Option<PathBuf>,
+    /// Read the hidden password from an environment variable
+    #[clap(long, value_name = "VAR")]
+    hidden_password_env: Option<String>,
+    /// Read the hidden password from the first line of an open file descriptor
+    #[clap(long, value_name = "N")]
+    hidden_password_fd: Option<u32>,
+    /// Read the hidden password from the first line printed by a shell command
+    #[clap(long, value_name = "COMMAND")]
+    hidden_password_command: Option<String>,
+}
+
+impl From<HiddenPasswordArgs> for PasswordArgs {
+    fn from(args: HiddenPasswordArgs) -> Self {
+        Self {
+            password: args.hidden_password,
+            password_file: args.hidden_password_file,
+            password_env: args.hidden_password_env,
+            password_fd: args.hidden_password_fd,
+            password_command: args.hidden_password_command,
+            prefix: "hidden-",
+        }
+    }
+}
+
+/// Where the password of the compared chest is read from, with the same
+/// sources as the chest password
+#[derive(Debug, Args)]
+#[group(multiple = false)]
+pub(crate) struct TargetPasswordArgs {
+    /// Password of the compared chest, the chest password if no source is given
+    #[clap(long, value_name = "PASSWORD")]
+    target_password: Option<String>,
+    /// Read the target password from the first line of a file
+    #[clap(long, value_name = "PATH")]
+    target_password_file: Option<PathBuf>,
+    /// Read the target password from an environment variable
+    #[clap(long, value_name = "VAR")]
+    target_password_env: Option<String>,
+    /// Read the target password from the first line of an open file descriptor
+    #[clap(long, value_name = "N")]
+    target_password_fd: Option<u32>,
+    /// Read the target password from the first line printed by a shell command
+    #[clap(long, value_name = "COMMAND")]
+    target_password_command: Option<String>,
+}
+
+impl From<TargetPasswordArgs> for PasswordArgs {
+    fn from(args: TargetPasswordArgs) -> Self {
+        Self {
+            password: args.target_password,
+            password_file: args.target_password_file,
+            password_env: args.target_password_env,
+            password_fd: args.target_password_fd,
+            password_command: args.target_password_command,
+            prefix: "target-",
+        }
+    }
+}
+
+/// Checks of a new password, for the chests it is set on
+#[derive(Debug, Args)]
+pub(crate) struct StrengthArgs {
+    /// Minimum strength of a new password, from 0 (guessable) to 4 (very strong)
+    #[clap(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(0..=4), value_name = "SCORE")]
+    pub(crate) min_score: u8,
+    /// Accept a new password weaker than --min-score
+    #[clap(long, action)]
+    pub(crate) allow_weak: bool,
+}
+
+impl Default for StrengthArgs {
+    fn default() -> Self {
+        Self {
+            min_score: 3,
+            allow_weak: false,
+        }
+    }
+}
+
+impl StrengthArgs {
+    /// Reject empty passwords, and ones below the minimum score unless weak
+    /// passwords are allowed
+    pub(crate) fn check(&self, password: &str) -> ChestResult<()> {
+        if password.is_empty() {
+            return Err(ChestError::EmptyPassword);
+        }
+        let strength = strength::estimate(password);
+        if strength.score < self.min_score && !self.allow_weak {
+            return Err(ChestError::WeakPassword {
+                score: strength.score,
+                min_score: self.min_score,
+                warning: strength.warning,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl PasswordArgs {
+    pub(crate) fn is_given(&self) -> bool {
+        self.password.is_some()
+            || self.password_file.is_some()
+            || self.password_env.is_some()
+            || self.password_fd.is_some()
+            || self.password_command.is_some()
+    }
+
+    /// Read the password of a new chest, prompting for it twice as a typo
+    /// would make the chest unreadable, and check its strength
+    pub(crate) fn read_new(&self, msg: &str, strength: &StrengthArgs) -> ChestResult<String> {
+        let password = match self.is_given() {
+            true => self.read(msg)?,
+            false => prompt_new(msg)?,
+        };
+        strength.check(&password)?;
+        Ok(password)
+    }
+
+    /// Read the password from the given source, or prompt for it with `msg`
+    pub(crate) fn read(&self, msg: &str) -> ChestResult<String> {
+        if let Some(password) = &self.password {
+            return Ok(password.clone());
+        }
+        if let Some(path) = &self.password_file {
+            return first_line(fs::read_to_string(path)?);
+        }
+        if let Some(var) = &self.password_env {
+            return env::var(var)
+                .map_err(|_| ChestError::Password(format!("{var} is not set or not UTF-8")));
+        }
+        if let Some(fd) = self.password_fd {
+            return read_fd(fd);
+        }
+        if let Some(command) = &self.password_command {
+            return run(command);
+        }
+        if !Term::stderr().is_term() {
+            let p = self.prefix;
+            return Err(ChestError::Password(format!(
+                "no terminal to prompt on, use --{p}password-file, --{p}password-env, --{p}password-fd or --{p}password-command"
+            )));
+        }
+        Ok(prompt(msg))
+    }
+}
+
+/// Prompt for a new password and its confirmation
+pub(crate) fn prompt_new(msg: &str) -> ChestResult<String> {
+    let password = PasswordArgs::default().read(msg)?;
+    if prompt(&format!("Confirm {}", msg.to_lowercase())) != password {
+        return Err(ChestError::PasswordMismatch);
+    }
+    Ok(password)
+}
+
+/// Password without the line ending and anything after it
+fn first_line(input: String) -> ChestResult<String> {
+    match input.lines().next() {
+        Some(line) => Ok(line.to_string()),
+        None => Err(ChestError::Password(
+            "the password source is empty".to_string(),
+        )),
+    }
+}
+
+/// Only the first line is read, the writer may keep the descriptor open
+#[cfg(unix)]
+fn read_fd(fd: u32) -> ChestResult<String> {
+    let error =
+        |e: io::Error| ChestError::Password(format!("file descriptor {fd} can't be read: {e}"));
+    let mut input = String::new();
+    BufReader::new(fs::File::open(format!("/dev/fd/{fd}")).map_err(error)?)
+        .read_line(&mut input)
+        .map_err(error)?;
+    first_line(input)
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: u32) -> ChestResult<String> {
+    Err(ChestError::Password(
+        "--password-fd is only supported on unix".to_string(),
+    ))
+}
+
+fn run(command: &str) -> ChestResult<String> {
+    let (shell, flag) = match cfg!(windows) {
+        true => ("cmd", "/C"),
+        false => ("sh", "-c"),
+    };
+    // stdin and stderr are left to the command, e.g. for a pinentry prompt
+    let output = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(ChestError::Password(format!(
+            "the password command failed with {}",
+            output.status
+        )));
+    }
+    let output = String::from_utf8(output.stdout).map_err(|_| {
+        ChestError::Password("the password command printed invalid UTF-8".to_string())
+    })?;
+    first_line(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_is_read_from_each_source() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "from file\nignored\n").unwrap();
+        let args = PasswordArgs {
+            password_file: Some(file.path().to_path_buf()),
+            ..PasswordArgs::default()
+        };
+        assert_eq!("from file", args.read("Password").unwrap());
+
+        let var = "CHEST_PASSWORD_IS_READ_FROM_EACH_SOURCE";
+        env::set_var(var, "from env");
+        let args = PasswordArgs {
+            password_env: Some(var.to_string()),
+            ..PasswordArgs::default()
+        };
+        let password = args.read("Password");
+        env::remove_var(var);
+        assert_eq!("from env", password.unwrap());
+
+        let args = PasswordArgs {
+            password_command: Some("echo from command".to_string()),
+            ..PasswordArgs::default()
+        };
+        assert_eq!("from command", args.read("Password").unwrap());
+        let args = PasswordArgs {
+            password_command: Some("exit 3".to_string()),
+            ..PasswordArgs::default()
+        };
+        assert!(matches!(
+            args.read("Password"),
+            Err(ChestError::Password(_))
+        ));
+    }
+
+    #[test]
+    fn other_passwords_have_their_own_sources() {
+        use clap::Parser;
+
+        use crate::cli::{Cli, Commands};
+
+        let diff = |args: &[&str]| {
+            Cli::try_parse_from([&["chest", "diff", "a.chest", "b.chest"], args].concat())
+                .map(|cli| cli.command)
+        };
+        let Ok(Commands::Diff {
+            password,
+            target_password,
+            ..
+        }) = diff(&[
+            "--password-env",
+            "A",
+            "--target-password-command",
+            "echo target",
+        ])
+        else {
+            panic!("diff arguments weren't parsed");
+        };
+        assert_eq!(Some("A".to_string()), password.password_env);
+        let target_password = PasswordArgs::from(target_password);
+        assert!(target_password.is_given());
+        assert_eq!("target", target_password.read("Target password").unwrap());
+        assert!(diff(&["--target-password", "a", "--target-password-env", "B"]).is_err());
+    }
+
+    #[test]
+    fn new_passwords_must_be_strong_enough() {
+        let strength = StrengthArgs::default();
+        assert!(matches!(strength.check(""), Err(ChestError::EmptyPassword)));
+        assert!(matches!(
+            strength.check("password1"),
+            Err(ChestError::WeakPassword { score: 0, .. })
+        ));
+        assert!(strength.check("gravel otter lantern quietly").is_ok());
+        let allow_weak = StrengthArgs {
+            allow_weak: true,
+            ..StrengthArgs::default()
+        };
+        assert!(allow_weak.check("password1").is_ok());
+        assert!(allow_weak.check("").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use serde::Serialize;
+
+use crate::chest::{Snapshot, UnlockedChest, UnlockedFile};
+use crate::conflict::Plan;
+use crate::dedup::DedupStats;
+use crate::diff::Difference;
+use crate::listing::{format_method, format_time, to_hex};
+use crate::salvage::Lost;
+
+#[derive(Serialize)]
+pub(crate) struct NewReport {
+    pub(crate) path: String,
+    pub(crate) files: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct PeekReport {
+    pub(crate) compression_algorithm: Option<String>,
+    pub(crate) compression_level: Option<i32>,
+    pub(crate) layout: String,
+    pub(crate) dictionary_bytes: Option<u64>,
+    pub(crate) redundancy_percent: Option<u8>,
+    /// Number of decoy files, `None` unless the chest is padded
+    pub(crate) decoys: Option<usize>,
+    pub(crate) key_derivation_algorithm: String,
+    pub(crate) encryption_algorithm: String,
+    pub(crate) files: Vec<FileReport>,
+    pub(crate) totals: TotalsReport,
+    pub(crate) dedup: Option<DedupReport>,
+    pub(crate) snapshots: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DedupReport {
+    #[serde(flatten)]
+    pub(crate) stats: DedupStats,
+    pub(crate) saved_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileReport {
+    pub(crate) name: String,
+    pub(crate) size_bytes: u64,
+    pub(crate) stored_bytes: Option<u64>,
+    pub(crate) compression: String,
+    pub(crate) modified: Option<String>,
+    pub(crate) sha256: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct TotalsReport {
+    pub(crate) files: usize,
+    pub(crate) size_bytes: u64,
+    pub(crate) stored_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct OpenReport {
+    pub(crate) out: String,
+    pub(crate) files: Vec<String>,
+    pub(crate) renamed: Vec<RenamedReport>,
+    /// Existing files that were kept
+    pub(crate) skipped: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RenamedReport {
+    pub(crate) name: String,
+    /// Name it was extracted as
+    pub(crate) renamed_to: String,
+}
+
+impl RenamedReport {
+    pub(crate) fn from_plan(plan: &Plan) -> Vec<Self> {
+        plan.renamed
+            .iter()
+            .map(|(name, renamed_to)| RenamedReport {
+                name: name.clone(),
+                renamed_to: renamed_to.clone(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct SalvageReport {
+    pub(crate) out: String,
+    pub(crate) files: Vec<String>,
+    pub(crate) renamed: Vec<RenamedReport>,
+    pub(crate) skipped: Vec<String>,
+    pub(crate) lost: Vec<Lost>,
+    /// Damage found in the chest and parts of it that couldn't be decrypted
+    pub(crate) damage: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CatReport {
+    pub(crate) file: String,
+    pub(crate) size_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct VerifyReport {
+    pub(crate) files: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CheckReport {
+    pub(crate) checksums: bool,
+    pub(crate) redundancy_percent: Option<u8>,
+    pub(crate) blocks: usize,
+    pub(crate) size_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RepairReport {
+    pub(crate) repaired: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct HideReport {
+    pub(crate) path: String,
+    pub(crate) files: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SnapshotReport {
+    pub(crate) path: String,
+    pub(crate) snapshot: usize,
+    pub(crate) files: usize,
+    pub(crate) stored_files: usize,
+    pub(crate) shared_files: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SyncReport {
+    pub(crate) path: String,
+    pub(crate) added: Vec<String>,
+    pub(crate) replaced: Vec<String>,
+    pub(crate) deleted: Vec<String>,
+    pub(crate) unchanged: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DiffReport {
+    pub(crate) files: Vec<DiffEntryReport>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DiffEntryReport {
+    pub(crate) name: String,
+    pub(crate) difference: Difference,
+    /// Unified diff lines, only for modified text files with --content
+    pub(crate) content: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct LogReport {
+    pub(crate) snapshots: Vec<LogEntryReport>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct LogEntryReport {
+    pub(crate) snapshot: usize,
+    pub(crate) created: String,
+    pub(crate) files: usize,
+    pub(crate) new_files: usize,
+    pub(crate) size_bytes: u64,
+}
+
+impl PeekReport {
+    pub(crate) fn new(chest: &UnlockedChest, files: &[&UnlockedFile]) -> Self {
+        Self {
+            compression_algorithm: chest
+                .public
+                .compression_algorithm
+                .as_ref()
+                .map(|a| format!("{a:?}")),
+            compression_level: chest.public.compression_level,
+            layout: format!("{:?}", chest.public.layout).to_lowercase(),
+            dictionary_bytes: chest.dictionary.as_ref().map(|d| d.len() as u64),
+            redundancy_percent: chest.redundancy,
+            decoys: chest.padding.as_ref().map(|p| p.decoy_count()),
+            key_derivation_algorithm: format!("{:?}", chest.public.key_derivation_algorithm),
+            encryption_algorithm: format!("{:?}", chest.public.encryption_algorithm),
+            files: files.iter().map(|f| FileReport::new(f)).collect(),
+            totals: TotalsReport {
+                files: files.len(),
+                size_bytes: files.iter().map(|f| f.metadata.size_bytes).sum(),
+                stored_bytes: chest.stored_bytes(),
+            },
+            dedup: chest.dedup_stats().map(|stats| DedupReport {
+                saved_bytes: stats.saved_bytes(),
+                stats,
+            }),
+            snapshots: chest.snapshots.len(),
+        }
+    }
+}
+
+impl FileReport {
+    pub(crate) fn new(file: &UnlockedFile) -> Self {
+        Self {
+            name: file.metadata.filename.clone(),
+            size_bytes: file.metadata.size_bytes,
+            stored_bytes: file.stored_bytes(),
+            compression: format_method(file),
+            modified: file.metadata.modified.map(|m| format_time(Some(m))),
+            sha256: file.metadata.sha256.map(|sha256| to_hex(&sha256)),
+        }
+    }
+}
+
+impl LogEntryReport {
+    pub(crate) fn new(chest: &UnlockedChest, number: usize) -> Self {
+        let snapshot: &Snapshot = &chest.snapshots[number - 1];
+        let previous = number
+            .checked_sub(2)
+            .map(|i| chest.snapshots[i].files.as_slice())
+            .unwrap_or_default();
+        Self {
+            snapshot: number,
+            created: format_time(Some(snapshot.created)),
+            files: snapshot.files.len(),
+            new_files: snapshot
+                .files
+                .iter()
+                .filter(|i| !previous.contains(i))
+                .count(),
+            size_bytes: snapshot
+                .files
+                .iter()
+                .map(|i| chest.files[*i].metadata.size_bytes)
+                .sum(),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::chest::{sha256, validate_filename, LockedChest, UnlockedChest, UnlockedFile};
+use crate::conflict;
+use crate::container::{self, Damage};
+use crate::error::ChestResult;
+
+/// File that couldn't be extracted, and why
+#[derive(Debug, Serialize)]
+pub(crate) struct Lost {
+    pub(crate) name: String,
+    pub(crate) reason: String,
+}
+
+/// Read whatever can be read of a damaged chest file, repairing it first if
+/// it has enough parity blocks, with descriptions of the damage found
+pub(crate) fn read_chest(file: &[u8]) -> ChestResult<(LockedChest, Vec<String>)> {
+    let checked = container::check(file)?;
+    if checked.damage.is_empty() {
+        return Ok((LockedChest::from_partial(checked.body)?.0, Vec::new()));
+    }
+    // blocks past the end of a truncated file are only reported as truncation
+    let mut damage = checked
+        .damage
+        .iter()
+        .filter(|d| match d {
+            Damage::Block { start, .. } | Damage::ParityBlock { start, .. } => {
+                *start < file.len() as u64
+            }
+            _ => true,
+        })
+        .map(|d| format!("Damaged {d}"))
+        .collect::<Vec<_>>();
+    if checked.redundancy.is_some() {
+        if let Ok(repaired) = container::repair(file) {
+            let (chest, _) = LockedChest::from_partial(container::check(&repaired)?.body)?;
+            damage.push("Repaired the damage with parity blocks".to_string());
+            return Ok((chest, damage));
+        }
+    }
+    let (chest, problem) = LockedChest::from_partial(checked.body)?;
+    damage.extend(problem);
+    Ok((chest, damage))
+}
+
+/// Split off the files whose name would be written outside of the output
+/// folder
+pub(crate) fn valid_files(files: Vec<&UnlockedFile>) -> (Vec<&UnlockedFile>, Vec<Lost>) {
+    let mut lost = Vec::new();
+    let files = files
+        .into_iter()
+        .filter(|f| match validate_filename(&f.metadata.filename) {
+            Ok(()) => true,
+            Err(e) => {
+                lost.push(Lost {
+                    name: f.metadata.filename.clone(),
+                    reason: e.to_string(),
+                });
+                false
+            }
+        })
+        .collect();
+    (files, lost)
+}
+
+/// Extract every file that decrypts and matches its size and hash, files
+/// are decrypted in memory so nothing unverified is written
+pub(crate) fn extract(
+    chest: &UnlockedChest,
+    files: &[(&UnlockedFile, PathBuf)],
+) -> ChestResult<(Vec<String>, Vec<Lost>)> {
+    let mut extracted = Vec::new();
+    let mut lost = Vec::new();
+    for (f, path) in files {
+        let name = f.metadata.filename.clone();
+        let mut payload = Vec::new();
+        let reason = match chest.decrypt_file_to_writer(f, &mut payload) {
+            Err(e) => Some(e.to_string()),
+            Ok(_) if payload.len() as u64 != f.metadata.size_bytes => Some(format!(
+                "Size is {} bytes, expected {}",
+                payload.len(),
+                f.metadata.size_bytes
+            )),
+            Ok(_) if f.metadata.sha256.is_some_and(|s| s != sha256(&payload)) => {
+                Some("SHA-256 doesn't match".to_string())
+            }
+            Ok(_) => None,
+        };
+        if let Some(reason) = reason {
+            lost.push(Lost { name, reason });
+            continue;
+        }
+        conflict::create(path)?.write_all(&payload)?;
+        extracted.push(name);
+    }
+    Ok((extracted, lost))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::chest::{CompressionAlgorithm, Layout};
+
+    #[test]
+    fn truncated_chest_keeps_leading_files() {
+        let mut chest = UnlockedChest::new(
+            "password",
+            Some(CompressionAlgorithm::Zstd),
+            None,
+            Layout::Blobs,
+        )
+        .unwrap();
+        let payloads = (0..4)
+            .map(|i| {
+                (0..20_000)
+                    .map(|j| (i * 7 + j * 13) as u8)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        payloads.iter().enumerate().for_each(|(i, p)| {
+            chest
+                .add_file_from_reader(&p[..], format!("file{i}"), None)
+                .unwrap();
+        });
+        let body = bincode::serialize(&chest.lock("password").unwrap()).unwrap();
+        let file = container::wrap(&body, None).unwrap();
+        let (locked, problems) = read_chest(&file[..file.len() * 2 / 3]).unwrap();
+        assert!(problems.iter().any(|p| p.contains("cut short")));
+        let (unlocked, _) = locked.unlock_salvaging("password").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path();
+        let files = unlocked
+            .files
+            .iter()
+            .map(|f| (f, out.join(&f.metadata.filename)))
+            .collect::<Vec<_>>();
+        let (extracted, _) = extract(&unlocked, &files).unwrap();
+        assert!(!extracted.is_empty() && extracted.len() < 4);
+        extracted.iter().for_each(|name| {
+            let i = name[4..].parse::<usize>().unwrap();
+            assert_eq!(payloads[i], fs::read(out.join(name)).unwrap());
+        });
+    }
+
+    #[test]
+    fn wrong_password_salvages_nothing() {
+        let mut chest = UnlockedChest::new("password", None, None, Layout::Blobs).unwrap();
+        chest
+            .add_file_from_reader(&b"data"[..], "data".to_string(), None)
+            .unwrap();
+        let locked = chest.lock("password").unwrap();
+        assert!(locked.unlock_salvaging("wrong").is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+
+// passwords longer than this are not analysed further, they are strong enough
+const MAX_ANALYSED: usize = 64;
+// log10 of the guesses needed for each score, as in zxcvbn
+const SCORE_THRESHOLDS: [f64; 4] = [3.0, 6.0, 8.0, 10.0];
+// guesses per brute forced character whatever classes it uses, as in zxcvbn,
+// so a symbol or digit tacked on a word doesn't make it strong
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+
+// most common first, a word is as cheap as its rank in its list
+const PASSWORDS: &str = include_str!("strength/passwords.txt");
+const NAMES: &str = include_str!("strength/names.txt");
+const ENGLISH: &str = include_str!("strength/english.txt");
+
+static DICTIONARIES: Lazy<Vec<HashMap<&'static str, usize>>> = Lazy::new(|| {
+    [PASSWORDS, NAMES, ENGLISH]
+        .iter()
+        .map(|list| {
+            let mut ranks = HashMap::new();
+            list.lines().enumerate().for_each(|(rank, word)| {
+                ranks.entry(word).or_insert(rank);
+            });
+            ranks
+        })
+        .collect()
+});
+
+// US keyboard rows, unshifted and shifted, with the offset of their first key
+const KEYBOARD: [(&str, &str, f64); 4] = [
+    ("`1234567890-=", "~!@#$%^&*()_+", 0.0),
+    ("qwertyuiop[]\\", "QWERTYUIOP{}|", 1.5),
+    ("asdfghjkl;'", "ASDFGHJKL:\"", 1.75),
+    ("zxcvbnm,./", "ZXCVBNM<>?", 2.25),
+];
+// keys a keyboard pattern can start on and their average neighbour count,
+// as in zxcvbn
+const KEYBOARD_STARTS: f64 = 94.0;
+const KEYBOARD_DEGREE: f64 = 4.6;
+
+/// Strength of a password, from 0 (guessable) to 4 (very strong)
+pub(crate) struct Strength {
+    pub(crate) score: u8,
+    /// Why the password is weak, its weakest pattern
+    pub(crate) warning: &'static str,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pattern {
+    Common,
+    Keyboard,
+    Sequence,
+    Repeat,
+    Year,
+    Bruteforce,
+}
+
+impl Pattern {
+    fn warning(self) -> &'static str {
+        match self {
+            Pattern::Common => "it contains a common password, name or word",
+            Pattern::Keyboard => "it follows keys next to each other, like qwerty or 1q2w3e",
+            Pattern::Sequence => "it contains a sequence like abc or 1234",
+            Pattern::Repeat => "it repeats characters",
+            Pattern::Year => "it contains a year",
+            Pattern::Bruteforce => "it is too short",
+        }
+    }
+}
+
+/// Estimate in the style of zxcvbn: the password is split into the cheapest
+/// patterns an attacker would try, common passwords, names and words,
+/// keyboard patterns, alphabet sequences, repeats, years or brute force over
+/// the character classes it uses, and the number of guesses they need is
+/// turned into a score
+pub(crate) fn estimate(password: &str) -> Strength {
+    let chars = password.chars().take(MAX_ANALYSED).collect::<Vec<_>>();
+    let n = chars.len();
+    // cheapest guesses (log10) and patterns to reach position j in k segments
+    let mut best = vec![vec![None::<(f64, Vec<Pattern>)>; n + 1]; n + 1];
+    best[0][0] = Some((0.0, Vec::new()));
+    for j in 1..=n {
+        for i in 0..j {
+            let Some((guesses, pattern)) = cheapest(&chars[i..j]) else {
+                continue;
+            };
+            for k in 0..j {
+                let Some((previous, patterns)) = &best[i][k] else {
+                    continue;
+                };
+                let total = previous + guesses;
+                if best[j][k + 1].as_ref().is_none_or(|(t, _)| total < *t) {
+                    let mut patterns = patterns.clone();
+                    patterns.push(pattern);
+                    best[j][k + 1] = Some((total, patterns));
+                }
+            }
+        }
+    }
+    // the attacker also has to guess how many patterns there are and their order
+    let (guesses, patterns) = best[n]
+        .iter()
+        .enumerate()
+        .filter_map(|(k, b)| {
+            let (guesses, patterns) = b.as_ref()?;
+            let order = (1..=k).map(|i| (i as f64).log10()).sum::<f64>();
+            Some((guesses + order, patterns.as_slice()))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .unwrap_or((0.0, &[]));
+    let score = SCORE_THRESHOLDS.iter().filter(|t| guesses >= **t).count() as u8;
+    let warning = patterns
+        .iter()
+        .find(|p| **p != Pattern::Bruteforce)
+        .unwrap_or(&Pattern::Bruteforce)
+        .warning();
+    Strength { score, warning }
+}
+
+/// Cheapest pattern matching the whole segment, in log10 guesses
+fn cheapest(segment: &[char]) -> Option<(f64, Pattern)> {
+    let len = segment.len();
+    [
+        (common(segment), Pattern::Common),
+        (keyboard(segment), Pattern::Keyboard),
+        (sequence(segment), Pattern::Sequence),
+        (repeat(segment), Pattern::Repeat),
+        (year(segment), Pattern::Year),
+        (
+            Some(len as f64 * BRUTEFORCE_CARDINALITY.log10()),
+            Pattern::Bruteforce,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(guesses, pattern)| Some((guesses?, pattern)))
+    .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+fn common(segment: &[char]) -> Option<f64> {
+    if segment.len() < 3 {
+        return None;
+    }
+    let lower = segment
+        .iter()
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<String>();
+    let lookup = |word: &str| {
+        DICTIONARIES
+            .iter()
+            .filter_map(|d| d.get(word))
+            .min()
+            .copied()
+    };
+    // digits and symbols are taken as letters only if the word isn't listed
+    // as is, 1 being either an i or an l
+    let (rank, substituted) = match lookup(&lower) {
+        Some(rank) => (Some(rank), 0),
+        None => {
+            let substituted = lower.chars().filter(|c| unleet(*c, 'i') != *c).count();
+            let rank = ['i', 'l']
+                .iter()
+                .filter_map(|one| {
+                    lookup(&lower.chars().map(|c| unleet(c, *one)).collect::<String>())
+                })
+                .min();
+            (rank, substituted)
+        }
+    };
+    let (rank, variations) = match rank {
+        Some(rank) => (rank, 0.0),
+        None => (
+            lookup(&lower.chars().rev().collect::<String>())?,
+            2f64.log10(),
+        ),
+    };
+    let upper = segment.iter().filter(|c| c.is_uppercase()).count();
+    let capitalized = upper == 1 && segment[0].is_uppercase() || upper == segment.len();
+    let upper = match (upper, capitalized) {
+        (0, _) => 0.0,
+        (_, true) => 2f64.log10(),
+        (upper, false) => upper as f64 * 2f64.log10(),
+    };
+    Some(((rank + 1) as f64).log10() + variations + upper + substituted as f64 * 2f64.log10())
+}
+
+fn unleet(c: char, one: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '3' => 'e',
+        '1' => one,
+        '!' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
+
+/// Keys next to each other on the keyboard, in any direction, e.g. qwerty
+/// or zaq12wsx, costing more for each turn and shifted key as in zxcvbn
+fn keyboard(segment: &[char]) -> Option<f64> {
+    if segment.len() < 3 {
+        return None;
+    }
+    let keys = segment
+        .iter()
+        .map(|c| key(*c))
+        .collect::<Option<Vec<_>>>()?;
+    let mut turns = 0;
+    let mut direction = None;
+    for pair in keys.windows(2) {
+        let ((row, x, _), (next_row, next_x, _)) = (pair[0], pair[1]);
+        let adjacent = match row.abs_diff(next_row) {
+            0 => (next_x - x).abs() == 1.0,
+            1 => (next_x - x).abs() < 1.0,
+            _ => false,
+        };
+        if !adjacent {
+            return None;
+        }
+        let next = Some((next_row as i64 - row as i64, next_x > x));
+        if next != direction {
+            turns += 1;
+            direction = next;
+        }
+    }
+    let len = segment.len();
+    let mut guesses = 0.0;
+    for i in 2..=len {
+        for j in 1..=turns.min(i - 1) {
+            guesses += binomial(i - 1, j - 1) * KEYBOARD_STARTS * KEYBOARD_DEGREE.powi(j as i32);
+        }
+    }
+    let shifted = keys.iter().filter(|(_, _, shifted)| *shifted).count();
+    let shifts = match shifted {
+        0 => 1.0,
+        s if s == len => 2.0,
+        s => (1..=s.min(len - s)).map(|i| binomial(len, i)).sum(),
+    };
+    Some((guesses * shifts).log10())
+}
+
+/// Row, horizontal position and whether shift is held for a keyboard key
+fn key(c: char) -> Option<(usize, f64, bool)> {
+    KEYBOARD
+        .iter()
+        .enumerate()
+        .find_map(|(row, (keys, shifted, offset))| {
+            let (column, shifted) = match keys.chars().position(|k| k == c) {
+                Some(column) => (column, false),
+                None => (shifted.chars().position(|k| k == c)?, true),
+            };
+            Some((row, offset + column as f64, shifted))
+        })
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k).map(|i| (n - i) as f64 / (i + 1) as f64).product()
+}
+
+/// Alphabet or digit sequence, forwards or backwards
+fn sequence(segment: &[char]) -> Option<f64> {
+    if segment.len() < 3 {
+        return None;
+    }
+    let deltas = segment
+        .windows(2)
+        .map(|w| w[1] as i64 - w[0] as i64)
+        .collect::<Vec<_>>();
+    if !deltas.iter().all(|d| *d == deltas[0]) || deltas[0].abs() != 1 {
+        return None;
+    }
+    let start = match segment[0] {
+        'a' | 'A' | '0' | '1' | 'q' | 'Q' => 4.0,
+        c if c.is_ascii_digit() => 10.0,
+        _ => 26.0,
+    };
+    Some((start * segment.len() as f64 * 2.0).log10())
+}
+
+/// Short block repeated at least twice, e.g. aaaa or abcabc
+fn repeat(segment: &[char]) -> Option<f64> {
+    let len = segment.len();
+    (1..=len / 2)
+        .filter(|block| len % block == 0)
+        .find(|block| segment.chunks(*block).all(|c| c == &segment[..*block]))
+        .map(|block| {
+            let block_guesses = cheapest(&segment[..block]).map_or(0.0, |(g, _)| g);
+            block_guesses + ((len / block) as f64).log10()
+        })
+}
+
+fn year(segment: &[char]) -> Option<f64> {
+    let year = segment.iter().collect::<String>().parse::<u32>().ok()?;
+    (segment.len() == 4 && (1900..=2099).contains(&year)).then(|| 200f64.log10())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_patterns_are_weak() {
+        [
+            "",
+            "password",
+            "P@ssw0rd",
+            "qwerty123",
+            "aaaaaaaaaa",
+            "abcdef2024",
+            "liverpool1",
+            "jonathan1987",
+            "mercedes",
+            "zaq12wsx",
+            "1q2w3e4r",
+        ]
+        .iter()
+        .for_each(|password| {
+            let strength = estimate(password);
+            assert!(strength.score <= 1, "{password} scored {}", strength.score);
+        });
+        assert_eq!(Pattern::Common.warning(), estimate("Password1").warning);
+        assert_eq!(Pattern::Sequence.warning(), estimate("abcdefgh").warning);
+        assert_eq!(Pattern::Keyboard.warning(), estimate("zxcvfdsa").warning);
+    }
+
+    #[test]
+    fn leet_common_passwords_are_weak() {
+        [
+            "Tr0ub4dor&3",
+            "P@ssw0rd1!",
+            "M0nk3y!23",
+            "Dr4g0n2024!",
+            "5unsh1ne!",
+            "Sh4d0w#99",
+            "L1verp00l#1",
+            "W3lc0me!",
+            "App1e$",
+        ]
+        .iter()
+        .for_each(|password| {
+            let strength = estimate(password);
+            assert!(strength.score < 3, "{password} scored {}", strength.score);
+        });
+    }
+
+    #[test]
+    fn long_or_random_passwords_are_strong() {
+        [
+            "x7#kQ9!vLm2@pR",
+            "gravel otter lantern quietly",
+            "correct horse battery staple",
+        ]
+        .iter()
+        .for_each(|password| {
+            let strength = estimate(password);
+            assert!(strength.score >= 3, "{password} scored {}", strength.score);
+        });
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::time::UNIX_EPOCH;
+
+use crate::chest::{sha256, UnlockedChest};
+use crate::error::ChestResult;
+use crate::walk::Entry;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Change {
+    Added,
+    Replaced,
+    /// Same content with a different modification time, the blob is kept
+    Touched,
+    Unchanged,
+}
+
+/// Bring the chest file up to date with the directory entry, only reading it
+/// when its size or modification time changed
+pub(crate) fn sync_entry(chest: &mut UnlockedChest, entry: &Entry) -> ChestResult<Change> {
+    let file = fs::File::open(&entry.path)?;
+    let modified = file.metadata()?.modified().ok();
+    let size_bytes = file.metadata()?.len();
+    let modified_secs = modified
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+    let Some(index) = chest
+        .files
+        .iter()
+        .position(|f| f.metadata.filename == entry.name)
+    else {
+        chest.add_file_from_reader(file, entry.name.clone(), modified)?;
+        return Ok(Change::Added);
+    };
+    let metadata = &chest.files[index].metadata;
+    if metadata.size_bytes == size_bytes && metadata.modified == modified_secs {
+        return Ok(Change::Unchanged);
+    }
+    let payload = fs::read(&entry.path)?;
+    if metadata.size_bytes == payload.len() as u64
+        && chest.content_sha256(&chest.files[index])? == sha256(&payload)
+    {
+        chest.files[index].metadata.modified = modified_secs;
+        return Ok(Change::Touched);
+    }
+    chest.remove_file(&entry.name)?;
+    chest.add_file_from_reader(&payload[..], entry.name.clone(), modified)?;
+    Ok(Change::Replaced)
+}
+
+/// Names of the chest files missing from the directory entries
+pub(crate) fn removed(chest: &UnlockedChest, entries: &[Entry]) -> Vec<String> {
+    chest
+        .files
+        .iter()
+        .map(|f| &f.metadata.filename)
+        .filter(|name| !entries.iter().any(|e| &e.name == *name))
+        .cloned()
+        .collect()
+}
@@ -1,34 +1,142 @@
-use std::{io::Write, process::exit};
+use std::{io::Write, process::exit, sync::Mutex};
 
-use console::{Style, Term};
-use once_cell::sync::Lazy;
+use clap::ValueEnum;
+use console::{strip_ansi_codes, Style, Term};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use serde_json::json;
 
 pub(crate) static DANGER: Lazy<Style> = Lazy::new(|| Style::new().red());
 pub(crate) static INFO: Lazy<Style> = Lazy::new(|| Style::new().blue());
 pub(crate) static SUCCESS: Lazy<Style> = Lazy::new(|| Style::new().green());
 
-pub(crate) fn fatal(msg: &str, code: i32) -> ! {
-    let prefix = DANGER.apply_to("!");
-    let out = Term::stdout();
-    _ = out.write_line(&format!("{prefix} {msg}"));
+static OUTPUT: OnceCell<Output> = OnceCell::new();
+// warnings given to the single JSON document once the command is done
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human readable, styled output
+    #[default]
+    Text,
+    /// A single JSON document once the command is done
+    Json,
+    /// One JSON event per line while the command runs
+    Ndjson,
+}
+
+#[derive(Clone, Copy)]
+struct Output {
+    format: OutputFormat,
+    // when stdout carries file data, structured output goes to stderr
+    data_on_stdout: bool,
+}
+
+pub(crate) fn init(format: OutputFormat, data_on_stdout: bool) {
+    _ = OUTPUT.set(Output {
+        format,
+        data_on_stdout,
+    });
+}
+
+fn output() -> Output {
+    OUTPUT.get().copied().unwrap_or(Output {
+        format: OutputFormat::Text,
+        data_on_stdout: false,
+    })
+}
+
+fn structured_term() -> Term {
+    match output().data_on_stdout {
+        true => Term::stderr(),
+        false => Term::stdout(),
+    }
+}
+
+fn warnings() -> Vec<String> {
+    WARNINGS.lock().map(|w| w.clone()).unwrap_or_default()
+}
+
+fn event(kind: &str, msg: &str) {
+    if output().format == OutputFormat::Ndjson {
+        let event = json!({ "type": kind, "message": strip_ansi_codes(msg) });
+        _ = structured_term().write_line(&event.to_string());
+    }
+}
+
+pub(crate) fn fatal(msg: &str, error_code: &str, code: i32) -> ! {
+    let error = json!({ "code": error_code, "message": strip_ansi_codes(msg) });
+    match output().format {
+        OutputFormat::Text => {
+            let prefix = DANGER.apply_to("!");
+            let out = Term::stderr();
+            _ = out.write_line(&format!("{prefix} {msg}"));
+        }
+        OutputFormat::Json => {
+            let document = json!({ "ok": false, "error": error, "warnings": warnings() });
+            _ = structured_term().write_line(&document.to_string());
+        }
+        OutputFormat::Ndjson => {
+            let event = json!({ "type": "error", "error": error });
+            _ = structured_term().write_line(&event.to_string());
+        }
+    }
     exit(code);
 }
 
 pub(crate) fn info(msg: &str) {
+    if output().format != OutputFormat::Text {
+        return event("info", msg);
+    }
     let prefix = INFO.apply_to(">");
-    let out = Term::stdout();
+    let out = Term::stderr();
     _ = out.write_line(&format!("{prefix} {msg}"));
 }
 
 pub(crate) fn success(msg: &str) {
+    if output().format != OutputFormat::Text {
+        return event("success", msg);
+    }
     let prefix = SUCCESS.apply_to(">");
-    let out = Term::stdout();
+    let out = Term::stderr();
+    _ = out.write_line(&format!("{prefix} {msg}"));
+}
+
+pub(crate) fn warning(msg: &str) {
+    if output().format == OutputFormat::Json {
+        if let Ok(mut warnings) = WARNINGS.lock() {
+            warnings.push(strip_ansi_codes(msg).to_string());
+        }
+        return;
+    }
+    if output().format != OutputFormat::Text {
+        return event("warning", msg);
+    }
+    let prefix = DANGER.apply_to(">");
+    let out = Term::stderr();
     _ = out.write_line(&format!("{prefix} {msg}"));
 }
 
+pub(crate) fn print(msg: &str) {
+    if output().format != OutputFormat::Text {
+        return;
+    }
+    let out = Term::stdout();
+    _ = out.write_line(msg);
+}
+
+pub(crate) fn result<T: Serialize>(result: &T) {
+    let document = match output().format {
+        OutputFormat::Text => return,
+        OutputFormat::Json => json!({ "ok": true, "result": result, "warnings": warnings() }),
+        OutputFormat::Ndjson => json!({ "type": "result", "result": result }),
+    };
+    _ = structured_term().write_line(&document.to_string());
+}
+
 pub(crate) fn prompt(msg: &str) -> String {
     let prefix = INFO.apply_to("?");
-    let mut out = Term::stdout();
+    let mut out = Term::stderr();
     _ = out.write(format!("{prefix} {msg}: ").as_bytes());
     flush(&out);
     let input = out
@@ -36,14 +144,27 @@ pub(crate) fn prompt(msg: &str) -> String {
         .unwrap_or_default()
         .trim()
         .to_string();
-    remove_last_lines(1);
+    _ = out.clear_last_lines(1);
     let prefix = SUCCESS.apply_to("?");
     _ = out.write_line(&format!("{prefix} {msg}: "));
     input
 }
 
+/// Ask a yes or no question, anything but y or yes is a no
+pub(crate) fn confirm(msg: &str) -> bool {
+    let prefix = INFO.apply_to("?");
+    let mut out = Term::stderr();
+    _ = out.write(format!("{prefix} {msg} [y/N] ").as_bytes());
+    flush(&out);
+    let input = out.read_line().unwrap_or_default().trim().to_lowercase();
+    matches!(input.as_str(), "y" | "yes")
+}
+
 pub(crate) fn remove_last_lines(number: usize) {
-    let out = Term::stdout();
+    if output().format != OutputFormat::Text {
+        return;
+    }
+    let out = Term::stderr();
     _ = out.clear_last_lines(number);
 }
 
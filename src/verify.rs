@@ -0,0 +1,79 @@
+use std::io::{self, Write};
+
+use ring::digest;
+
+use crate::chest::{UnlockedChest, UnlockedFile};
+use crate::listing::to_hex;
+
+/// Writer hashing and counting the bytes written to it, so files can be
+/// checked without keeping their plaintext
+struct HashWriter {
+    context: digest::Context,
+    size_bytes: u64,
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.context.update(buf);
+        self.size_bytes += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decrypt and decompress the file in memory, checking its authentication
+/// tag, size and hash, return why it failed if it did
+pub(crate) fn verify_file(chest: &UnlockedChest, file: &UnlockedFile) -> Option<String> {
+    let mut writer = HashWriter {
+        context: digest::Context::new(&digest::SHA256),
+        size_bytes: 0,
+    };
+    if let Err(e) = chest.decrypt_file_to_writer(file, &mut writer) {
+        return Some(e.to_string());
+    }
+    if writer.size_bytes != file.metadata.size_bytes {
+        return Some(format!(
+            "Size is {} bytes, expected {}",
+            writer.size_bytes, file.metadata.size_bytes
+        ));
+    }
+    let sha256 = writer.context.finish();
+    // first format files have no recorded hash to check
+    match file.metadata.sha256 {
+        Some(expected) if sha256.as_ref() != expected => Some(format!(
+            "SHA-256 is {}, expected {}",
+            to_hex(sha256.as_ref()),
+            to_hex(&expected)
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chest::{CompressionAlgorithm, Layout};
+
+    #[test]
+    fn tampered_metadata_fails_verification() {
+        let mut chest = UnlockedChest::new(
+            "password",
+            Some(CompressionAlgorithm::Zstd),
+            None,
+            Layout::Blobs,
+        )
+        .unwrap();
+        chest
+            .add_file_from_reader(&b"some data"[..], "data.txt".to_string(), None)
+            .unwrap();
+        assert_eq!(None, verify_file(&chest, &chest.files[0]));
+        let mut file = chest.files[0].clone();
+        file.metadata.sha256.as_mut().unwrap()[0] ^= 1;
+        assert!(verify_file(&chest, &file).unwrap().starts_with("SHA-256"));
+        file.metadata.size_bytes = 1;
+        assert!(verify_file(&chest, &file).unwrap().starts_with("Size"));
+    }
+}
@@ -0,0 +1,36 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Regular file found in a directory, with its path relative to the
+/// directory as stored in chests
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+}
+
+/// Recursively list the regular files of the directory sorted by name,
+/// skipping symbolic links
+pub(crate) fn walk_dir<P: AsRef<Path>>(root: P) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    walk(root.as_ref(), "", &mut entries)?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn walk(dir: &Path, prefix: &str, entries: &mut Vec<Entry>) -> io::Result<()> {
+    fs::read_dir(dir)?.try_for_each(|entry| {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if file_type.is_dir() {
+            walk(&entry.path(), &format!("{name}/"), entries)?;
+        } else if file_type.is_file() {
+            entries.push(Entry {
+                name,
+                path: entry.path(),
+            });
+        }
+        Ok(())
+    })
+}